@@ -0,0 +1,24 @@
+use std::{env, fs, path::Path};
+
+// Embeds every file of `res` into the executable, see `BuiltinPack`.
+// Adding a texture or shader is then just a matter of dropping it into the directory.
+fn main() {
+    let root = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("res");
+    println!("cargo:rerun-if-changed={}", root.display());
+
+    let mut names: Vec<String> = fs::read_dir(&root).unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().unwrap().is_file())
+        .map(|entry| entry.file_name().into_string().unwrap())
+        .collect();
+
+    names.sort();
+
+    let mut source = String::from("&[\n");
+    for name in names {
+        source += &format!("    ({:?}, include_bytes!({:?})),\n", name, root.join(&name).display().to_string());
+    }
+
+    source += "]\n";
+    fs::write(Path::new(&env::var("OUT_DIR").unwrap()).join("builtin_resources.rs"), source).unwrap();
+}
@@ -0,0 +1,106 @@
+//Vertex
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+};
+
+// Texture region (min.x, min.y, max.x, max.y) and atlas page
+struct Tile {
+    rect: vec4<f32>;
+    page: u32;
+};
+
+// Every `TextureId`'s tile
+struct TileTable {
+    tiles: array<Tile, 256>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+[[group(2), binding(0)]]
+var<uniform> tile_table: TileTable;
+
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+};
+
+// Packed `ChunkVertex`, see `graphics::mesh` for the layout
+struct VertexInput {
+    [[location(0)]] data : vec2<u32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_pos : vec4<f32>;
+    [[location(0)]]       uv       : vec2<f32>;
+    [[location(1)]]       tile     : vec4<f32>;
+    [[location(2)]]       light    : vec2<f32>;
+    [[location(3)]]       ao       : f32;
+    [[location(4), interpolate(flat)]] page : u32;
+};
+
+[[stage(vertex)]]
+fn vertex_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    let pos   = vec3<u32>(in.data.x & 63u, (in.data.x >> 6u) & 63u, (in.data.x >> 12u) & 63u);
+    let ao    = (in.data.x >> 21u) & 3u;
+    let light = vec2<u32>((in.data.x >> 23u) & 15u, (in.data.x >> 27u) & 15u);
+    let uv    = vec2<i32>(i32(in.data.y & 127u) - 32, i32((in.data.y >> 7u) & 127u) - 32);
+    let tile  = in.data.y >> 14u;
+
+    var out: VertexOutput;
+    out.clip_pos = camera.view_proj * model_matrix * vec4<f32>(vec3<f32>(pos), 1.0);
+    out.uv       = vec2<f32>(uv);
+    out.tile     = tile_table.tiles[tile].rect;
+    out.page     = tile_table.tiles[tile].page;
+    out.light    = vec2<f32>(light) / 15.0;
+    out.ao       = f32(ao) / 3.0;
+
+    return out;
+}
+
+// Fragment
+[[group(0), binding(0)]]
+var t0: texture_2d_array<f32>;
+[[group(0), binding(1)]]
+var s0: sampler;
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    // Merged quads span several blocks, so repeat the tile once per block.
+    // Mip levels are picked from the unrepeated coordinates, otherwise tile edges would get the smallest one
+    let size = in.tile.zw - in.tile.xy;
+    let uv = in.tile.xy + fract(in.uv) * size;
+    let color = textureSampleGrad(t0, s0, uv, i32(in.page), dpdx(in.uv) * size, dpdy(in.uv) * size);
+
+    // Every light level is 80% as bright as the one above it, with a bit of ambient light so caves aren't pitch black
+    let level = max(in.light.x, in.light.y);
+    let brightness = 0.05 + 0.95 * pow(0.8, 15.0 * (1.0 - level));
+
+    // Fully occluded corners keep 40% of the light
+    let occlusion = 0.4 + 0.6 * in.ao;
+    return vec4<f32>(color.rgb * brightness * occlusion, color.a);
+}
+
+// Opaque and cutout blocks, cutout textures are either fully visible or not there at all
+[[stage(fragment)]]
+fn fragment_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = shade(in);
+    if (color.a < 0.5) {
+        discard;
+    }
+
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+[[stage(fragment)]]
+fn fragment_translucent(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in);
+}
@@ -18,13 +18,19 @@ struct InstanceInput {
 };
 
 struct VertexInput {
-    [[location(0)]] pos : vec3<f32>;
-    [[location(1)]] uv  : vec2<f32>;
+    [[location(0)]] pos   : vec3<f32>;
+    [[location(1)]] uv    : vec2<f32>;
+    [[location(2)]] tile  : vec4<f32>;
+    [[location(3)]] light : vec2<f32>;
+    [[location(4)]] ao    : f32;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_pos : vec4<f32>;
     [[location(0)]]       uv       : vec2<f32>;
+    [[location(1)]]       tile     : vec4<f32>;
+    [[location(2)]]       light    : vec2<f32>;
+    [[location(3)]]       ao       : f32;
 };
 
 [[stage(vertex)]]
@@ -39,6 +45,9 @@ fn vertex_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
     out.clip_pos = camera.view_proj * model_matrix * vec4<f32>(in.pos, 1.0);
     out.uv       = in.uv;
+    out.tile     = in.tile;
+    out.light    = in.light;
+    out.ao       = in.ao;
 
     return out;
 }
@@ -51,5 +60,15 @@ var s0: sampler;
 
 [[stage(fragment)]]
 fn fragment_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    return textureSample(t0, s0, in.uv);
+    // Merged quads span several blocks, so repeat the tile once per block
+    let uv = in.tile.xy + fract(in.uv) * (in.tile.zw - in.tile.xy);
+    let color = textureSample(t0, s0, uv);
+
+    // Every light level is 80% as bright as the one above it, with a bit of ambient light so caves aren't pitch black
+    let level = max(in.light.x, in.light.y);
+    let brightness = 0.05 + 0.95 * pow(0.8, 15.0 * (1.0 - level));
+
+    // Fully occluded corners keep 40% of the light
+    let occlusion = 0.4 + 0.6 * in.ao;
+    return vec4<f32>(color.rgb * brightness * occlusion, color.a);
 }
\ No newline at end of file
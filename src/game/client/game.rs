@@ -1,7 +1,7 @@
 use anyhow::Result;
 use winit::{window::Window, event::WindowEvent};
 
-use crate::state::State;
+use crate::{state::State, resources::ResourceManager};
 
 use super::screen::{world_screen::WorldScreen, menu_screen::MenuScreen};
 
@@ -15,7 +15,8 @@ impl Game {
             state: State::new(window).await?
         };
 
-        game.state.screen_stack.push(Box::new(WorldScreen::new(game.state.device.clone(), game.state.queue.clone(), &game.state.config)?));
+        let resources = ResourceManager::from_env()?;
+        game.state.screen_stack.push(Box::new(WorldScreen::new(game.state.device.clone(), game.state.queue.clone(), &game.state.config, &resources)?));
         game.state.screen_stack.push(Box::new(MenuScreen::new(&game.state.device, &game.state.config)?));
 
         return Ok(game);
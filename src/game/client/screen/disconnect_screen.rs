@@ -0,0 +1,48 @@
+use anyhow::Result;
+use winit::event::WindowEvent;
+use crate::{screen::Screen, egui::EGUI};
+
+/// Shown over the world once the connection to the server is lost.
+pub struct DisconnectScreen {
+    pub egui   : EGUI,
+    pub reason : Option<String>, // Hidden until set
+}
+
+impl DisconnectScreen {
+    pub fn new(device: &wgpu::Device, surface_format: &wgpu::SurfaceConfiguration) -> Result<Self> {
+        return Ok(Self {
+            egui   : EGUI::new(device, surface_format)?,
+            reason : None,
+        });
+    }
+
+    pub fn show(&mut self, reason: impl Into<String>) {
+        self.reason = Some(reason.into());
+    }
+}
+
+impl Screen for DisconnectScreen {
+    fn is_hidden(&mut self) -> bool { self.reason.is_none() }
+
+    fn render(&mut self, view: &wgpu::TextureView, queue: &wgpu::Queue, device: &wgpu::Device) {
+        let reason = self.reason.clone().unwrap_or_default();
+        self.egui.render(view, queue, device, |ctx| {
+            egui::Window::new("Disconnected").collapsible(false).resizable(false).show(ctx, |ui| {
+                ui.label(reason);
+                ui.label("Restart the game to reconnect.");
+            });
+        });
+    }
+
+    // The world below is frozen, so nothing gets through
+    fn mouse(&mut self, _delta: (f64, f64)) -> bool { false }
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        self.egui.input(event);
+
+        return false;
+    }
+
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.egui.resize(new_size);
+    }
+}
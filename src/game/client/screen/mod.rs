@@ -1,2 +1,3 @@
 pub mod menu_screen;
-pub mod world_screen;
\ No newline at end of file
+pub mod world_screen;
+pub mod disconnect_screen;
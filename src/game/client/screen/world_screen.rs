@@ -1,26 +1,40 @@
-use std::{rc::Rc, net::{SocketAddr, UdpSocket}, env, mem::size_of, collections::HashMap};
+use std::{rc::Rc, net::SocketAddr, env, collections::HashMap, sync::Arc};
 
 use crate::{
-    game::{client::world::{player_camera::PlayerCamera, chunk::{chunk::{Chunk, BlockState}, chunk_renderer::ChunkRenderer, chunk_mesh::block_face}, player::Player}, net::proto::{ClientPacket, ServerPacket}},
-    graphics::{bindable::Bindable, camera::Projection, depth_buffer::DepthBuffer, utils::{self, Side}, atlas::Atlas, drawable::Drawable, mesh::{Vertex, InstanceRaw, InstancedMesh, Instance}, texture::Texture },
+    game::{client::world::{player_camera::PlayerCamera, chunk::{chunk_renderer::ChunkRenderer, chunk_mesh::{block_face, MAX_AO}, chunk::{BlockState, Chunk}, chunk_loader::ChunkLoader}, player::Player, world::World, block_registry::{BlockRegistry, TextureId, FaceTransform}, light::Light, raycast}, net::{proto::{ClientPacket, ServerPacket, PROTOCOL_VERSION, KEEP_ALIVE_INTERVAL}, transport::{Transport, Channel}}},
+    graphics::{bindable::Bindable, camera::{Projection, calc_view_proj}, frustum::Frustum, depth_buffer::DepthBuffer, utils::{self, Side}, atlas::Atlas, drawable::Drawable, mesh::{Vertex, InstanceRaw, InstancedMesh, Instance}, texture::Texture },
     screen::Screen,
+    resources::ResourceManager,
 };
-use anyhow::Result;
+use anyhow::{Result, Context, bail};
 use cgmath::{Deg, Quaternion, vec3};
 use euclid::{Box2D, num::Zero};
 use log::{info, error};
 use rand::Rng;
 use uuid::Uuid as UUID;
-use wgpu::include_wgsl;
-use winit::event::{KeyboardInput, WindowEvent};
+use winit::event::{KeyboardInput, WindowEvent, ElementState, MouseButton, VirtualKeyCode};
+
+use super::disconnect_screen::DisconnectScreen;
+
+// How long to wait for the server to answer while joining
+const CONNECT_TIMEOUT: instant::Duration = instant::Duration::from_secs(5);
+
+// The server is considered gone after this long without hearing from it
+const SERVER_TIMEOUT: instant::Duration = instant::Duration::from_secs(10);
 
 pub struct WorldScreen {
     pub last_render    : instant::Instant,
     pub last_packet    : instant::Instant,
+    pub last_heartbeat : instant::Instant, // When the last `KeepAlive` was sent
+    pub last_heard     : instant::Instant, // Of the last message from the server
     pub chunk_renderer : ChunkRenderer,
-    pub chunk          : Chunk,
+    pub world          : World,
+    pub chunk_loader   : ChunkLoader,
+    pub registry       : Arc<BlockRegistry>,
+    pub selected_block : BlockState, // Placed with the right mouse button
     
-    pub socket         : UdpSocket,
+    pub transport      : Transport,
+    pub server         : SocketAddr,
 
     pub player         : Player,
     pub player_uuid    : UUID,
@@ -36,45 +50,53 @@ pub struct WorldScreen {
     pub projection     : Projection,
     pub camera         : PlayerCamera,
     pub depth_buffer   : DepthBuffer,
+
+    pub disconnect_screen : DisconnectScreen,
 }
 
 impl WorldScreen {
-    pub fn new(device: Rc<wgpu::Device>, queue: Rc<wgpu::Queue>, config: &wgpu::SurfaceConfiguration) -> Result<Self> {
+    pub fn new(device: Rc<wgpu::Device>, queue: Rc<wgpu::Queue>, config: &wgpu::SurfaceConfiguration, resources: &ResourceManager) -> Result<Self> {
         // Camera
         let projection = Projection::new(config.width, config.height, Deg(90.0), 0.1, 100.0);
-        let camera = PlayerCamera::new(&device);
+        let mut camera = PlayerCamera::new(&device);
 
         // Setup player mesh
         let mut player_mesh = InstancedMesh::new(&device, [
-            block_face(Side::Top,    0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into())),
-            block_face(Side::Bottom, 0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into())),
-            block_face(Side::Right,  0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into())),
-            block_face(Side::Left,   0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into())),
-            block_face(Side::Front,  0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into())),
-            block_face(Side::Back,   0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into())),
+            block_face(Side::Top,    0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into()), FaceTransform::default(), Light::FULL, [MAX_AO; 4]),
+            block_face(Side::Bottom, 0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into()), FaceTransform::default(), Light::FULL, [MAX_AO; 4]),
+            block_face(Side::Right,  0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into()), FaceTransform::default(), Light::FULL, [MAX_AO; 4]),
+            block_face(Side::Left,   0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into()), FaceTransform::default(), Light::FULL, [MAX_AO; 4]),
+            block_face(Side::Front,  0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into()), FaceTransform::default(), Light::FULL, [MAX_AO; 4]),
+            block_face(Side::Back,   0, 0, 0, Box2D::new((0.0, 0.0).into(), (1.0, 1.0).into()), FaceTransform::default(), Light::FULL, [MAX_AO; 4]),
         ].concat(), vec![]);
 
-        let player_texture = Texture::from_bytes(&device, &queue, include_bytes!("../../../../res/player.png"), wgpu::FilterMode::Nearest, "player")?;
+        let player_texture = Texture::from_image(&device, &queue, &resources.image("player.png")?, wgpu::FilterMode::Nearest, Some("player"))?;
 
         // Rendering
         let depth_buffer = DepthBuffer::new(&device, (config.width, config.height).into());
 
-        // Chunks, btw it should be about time we start using resource stores...
-        let image_test = image::load_from_memory(include_bytes!("../../../../res/test.png"))?.flipv();
-        let image_panel = image::load_from_memory(include_bytes!("../../../../res/panel.png"))?.flipv();
-        let texture_atlas = Atlas::new(&[
-            (BlockState::TEST , image_test ),
-            (BlockState::PANEL, image_panel)
-        ], &device, &queue, None);
+        // Chunks
+        let registry = Arc::new(BlockRegistry::load(resources)?);
+        let mut images = vec![];
+        for (id, name) in registry.textures().iter().enumerate() {
+            let image = resources.image(&format!("{}.png", name)).with_context(|| format!("Failed to load block texture {:?}", name))?;
+            images.push((id as TextureId, image));
+        }
+
+        let texture_atlas = Atlas::new(&images, &device, &queue, Some("Block Atlas"))?;
+
+        let chunk_renderer = ChunkRenderer::new(&device, &queue, config, camera.layout(), resources, registry.clone(), texture_atlas)?;
 
-        let chunk = Chunk::new();
-        let mut chunk_renderer = ChunkRenderer::new(texture_atlas);
-        chunk_renderer.add(&device, &chunk);
+        // Render distance in chunks
+        let view_distance = match env::var("VIEW_DISTANCE") {
+            Ok(view_distance) => view_distance.parse().context("VIEW_DISTANCE must be a number of chunks")?,
+            Err(_) => 4,
+        };
 
         // Shaders
-        let shader = device.create_shader_module(&include_wgsl!("../../../../res/core.wgsl"));
+        let shader = resources.shader(&device, "core.wgsl")?;
         let pipeline = utils::pipeline(&device, &shader, &config, &[
-            &chunk_renderer.texture_atlas.layout(), // TODO: move pipeline into ChunkRenderer?
+            player_texture.layout(),
             camera.layout(),
         ], &[
             Vertex::describe(),
@@ -88,17 +110,28 @@ impl WorldScreen {
             position : (0.0, 0.0, 0.0).into(),
         };
 
-        let port = generator.gen();
-        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], port))).unwrap();
-        socket.connect("127.0.0.1:16000").unwrap();
+        let server = SocketAddr::from(([127, 0, 0, 1], 16000));
+        let mut transport = Transport::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
 
-        // Query player list
-        let query_player_list_packet = bincode::serialize(&ClientPacket::QueryPlayerList).unwrap();
-        socket.send(&query_player_list_packet).unwrap();
+        // Join, chunks are streamed in by the server afterwards
+        let connect_packet = bincode::serialize(&ClientPacket::Connect {
+            protocol_version : PROTOCOL_VERSION,
+            name             : player.name.clone(),
+        }).unwrap();
 
-        let mut player_list_data = [0; 16384];
-        let player_list_data_read = socket.recv(&mut player_list_data).unwrap();
-        let player_list: HashMap<UUID, Player> = bincode::deserialize(&player_list_data[..player_list_data_read]).unwrap();
+        transport.send(server, Channel::Reliable, connect_packet);
+
+        let (player_uuid, player_token, world_info, player_list) = match bincode::deserialize(&Self::await_reply(&mut transport, server)?)? {
+            ServerPacket::JoinAccepted { uuid, token, world, players } => (uuid, token, world, players),
+            ServerPacket::JoinRejected { reason } => bail!("Server refused to let {} join: {}", player.name, reason),
+            _ => bail!("Unexpected server packet while joining"),
+        };
+
+        info!("World seed:\t{}", world_info.seed);
+        info!("Player UUID:\t{}", &player_uuid);
+        info!("Player token:\t{}", &player_token);
+
+        camera.camera.position = world_info.spawn;
 
         // update player mesh instances
         player_mesh.instances = player_list.iter().map(|player| {
@@ -109,33 +142,22 @@ impl WorldScreen {
         }).collect();
         player_mesh.bake_instances(&device);
 
-        // Send PlayerJoin
-        let join_packet = bincode::serialize(&ClientPacket::PlayerJoin {
-            name     : player.name.clone(),
-        }).unwrap();
-
-        socket.send(&join_packet).unwrap();
-
-        // Obtain player token (auth) and UUID
-        let mut player_data = [0; size_of::<UUID>() * 2];
-        if socket.recv(&mut player_data)? != size_of::<UUID>() * 2 {
-            panic!("Corrupted PlayerJoin packet response");
-        }
-
-        let player_uuid = UUID::from_bytes(unsafe { player_data[..16].try_into().unwrap_unchecked() });
-        let player_token = UUID::from_bytes(unsafe { player_data[16..].try_into().unwrap_unchecked() });
-        info!("Player UUID:\t{}", &player_uuid);
-        info!("Player token:\t{}", &player_token);
-
-        socket.set_nonblocking(true).unwrap();
+        let disconnect_screen = DisconnectScreen::new(&device, config)?;
+        let selected_block = registry.blocks().map(|block| block.id).find(|id| *id != BlockState::AIR).unwrap_or(BlockState::AIR);
         
         return Ok(Self {
             last_render: instant::Instant::now(),
             last_packet: instant::Instant::now(),
+            last_heartbeat: instant::Instant::now(),
+            last_heard: instant::Instant::now(),
             chunk_renderer,
-            chunk,
+            world: World::with_lighting(registry.clone()),
+            chunk_loader: ChunkLoader::new(view_distance),
+            registry,
+            selected_block,
 
-            socket,
+            transport,
+            server,
 
             player,
             player_uuid,
@@ -151,10 +173,48 @@ impl WorldScreen {
             projection,
             camera,
             depth_buffer,
+
+            disconnect_screen,
         });
     }
 }
 
+impl WorldScreen {
+    // Blocks until the next message from the server, resending whatever got lost on the way
+    fn await_reply(transport: &mut Transport, server: SocketAddr) -> Result<Vec<u8>> {
+        let deadline = instant::Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            let now = instant::Instant::now();
+            while let Some((address, bytes)) = transport.receive(now)? {
+                if address == server {
+                    return Ok(bytes);
+                }
+            }
+
+            if now >= deadline {
+                bail!("Server at {} didn't respond", server);
+            }
+
+            transport.flush(now);
+            transport.wait(instant::Duration::from_millis(10))?;
+        }
+    }
+
+    // Unloads far away chunks and asks the server for missing ones around the camera
+    fn load_chunks(&mut self) {
+        let positions = self.chunk_loader.update(&mut self.world, self.camera.camera.position);
+        if !positions.is_empty() {
+            let bytes = bincode::serialize(&ClientPacket::RequestChunks {
+                token     : self.player_token,
+                uuid      : self.player_uuid,
+                positions,
+            }).unwrap();
+
+            self.transport.send(self.server, Channel::Reliable, bytes);
+        }
+    }
+}
+
 impl Drop for WorldScreen {
     fn drop(&mut self) {
         let bytes = bincode::serialize(&ClientPacket::PlayerLeave {
@@ -162,7 +222,9 @@ impl Drop for WorldScreen {
             uuid  : self.player_uuid
         }).unwrap();
 
-        self.socket.send(&bytes).unwrap();
+        // Best effort, nobody is around to resend it
+        self.transport.send(self.server, Channel::Reliable, bytes);
+        self.transport.flush(instant::Instant::now());
     }
 }
 
@@ -170,18 +232,38 @@ impl Screen for WorldScreen {
     fn render(&mut self, view: &wgpu::TextureView, queue: &wgpu::Queue, device: &wgpu::Device) {
         utils::submit(&queue, device, |encoder| {
             utils::render(encoder, &view, Some(&self.depth_buffer.view), |mut render_pass| {
-                render_pass.set_pipeline(&self.pipeline);
                 self.camera.bind(&mut render_pass, 1);
-
                 self.chunk_renderer.draw(&mut render_pass);
 
+                render_pass.set_pipeline(&self.pipeline);
                 self.player_texture.bind(&mut render_pass, 0);
                 self.player_mesh.draw(&mut render_pass);
+
+                self.chunk_renderer.draw_translucent(&mut render_pass);
             });
         });
+
+        if !self.disconnect_screen.is_hidden() {
+            self.disconnect_screen.render(view, queue, device);
+        }
     }
 
     fn update(&mut self, now: instant::Instant) {
+        // Nothing changes anymore once disconnected
+        if !self.disconnect_screen.is_hidden() {
+            return;
+        }
+
+        if now - self.last_heartbeat >= KEEP_ALIVE_INTERVAL {
+            self.last_heartbeat = now;
+            let bytes = bincode::serialize(&ClientPacket::KeepAlive {
+                token : self.player_token,
+                uuid  : self.player_uuid,
+            }).unwrap();
+
+            self.transport.send(self.server, Channel::Unreliable, bytes);
+        }
+
         if now.duration_since(self.last_packet).as_millis() > 20 {
             self.last_packet = now;
             let bytes = bincode::serialize(&ClientPacket::PlayerMove {
@@ -190,17 +272,29 @@ impl Screen for WorldScreen {
                 position : self.camera.camera.position,
             }).unwrap();
             
-            self.socket.send(&bytes).unwrap();
+            self.transport.send(self.server, Channel::Unreliable, bytes);
         }
 
         let dt  = now - self.last_render;
         self.last_render = now;
         self.camera.update(&self.projection, &self.queue, dt);
-        
-        let mut buffer = [0; 16384];
-        if let Ok(read) = self.socket.recv(&mut buffer) {
-            if let Ok(packet) = bincode::deserialize::<ServerPacket>(&buffer[..read]) {
+        self.load_chunks();
+        self.chunk_renderer.update(&self.device, &mut self.world);
+        self.chunk_renderer.cull(&Frustum::from_matrix(&calc_view_proj(&self.camera.camera, &self.projection)));
+        self.chunk_renderer.sort_translucent(&self.queue, self.camera.camera.position);
+
+        // Chunks come in bursts, so handle everything that arrived since the last frame
+        while let Ok(Some((_, bytes))) = self.transport.receive(now) {
+            self.last_heard = now;
+            if let Ok(packet) = bincode::deserialize::<ServerPacket>(&bytes) {
                 match packet {
+                    // Only there to update `last_heard`
+                    ServerPacket::KeepAlive => {}
+
+                    ServerPacket::JoinAccepted { .. } | ServerPacket::JoinRejected { .. } => {
+                        error!("Invalid server packet: already joined");
+                    }
+
                     ServerPacket::PlayerJoin { uuid, player } => {
                         self.player_list.insert(uuid, player);
 
@@ -240,21 +334,94 @@ impl Screen for WorldScreen {
                             self.player_mesh.update_instances(&data, &self.queue);
                         } else { error!("Invalid server packet: no player with UUID: {}", uuid); }
                     }
+
+                    ServerPacket::BlockChange { position, block } => {
+                        // Marks the affected chunks dirty, the chunk renderer remeshes them
+                        self.world.set_block(position, block);
+                    }
+
+                    ServerPacket::ChunkData { position, data } => {
+                        if self.chunk_loader.received(position) {
+                            match Chunk::decompress(&data) {
+                                Ok(chunk) => self.world.insert(position, chunk),
+                                Err(error) => error!("Invalid server packet: corrupt chunk {:?}: {}", position, error),
+                            }
+                        }
+                    }
                 }
             } else { error!("Invalid server packet: corrupt data"); }
         }
 
+        if now - self.last_heard >= SERVER_TIMEOUT {
+            error!("Lost connection to the server");
+            self.disconnect_screen.show(format!("Server at {} stopped responding.", self.server));
+        }
+
+        self.transport.flush(now);
     }
 
     fn mouse(&mut self, delta: (f64, f64)) -> bool {
+        if !self.disconnect_screen.is_hidden() {
+            return self.disconnect_screen.mouse(delta);
+        }
+
         self.camera.on_mouse(delta.0, delta.1);
         return true;
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
+        if !self.disconnect_screen.is_hidden() {
+            return self.disconnect_screen.input(event);
+        }
+
         match event {
             WindowEvent::KeyboardInput { input: KeyboardInput { virtual_keycode: Some(key), state, .. }, .. } => {
                 self.camera.on_keyboard(*key, *state);
+
+                // Number keys pick the block to place, in registry order
+                let hotbar = [
+                    VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3,
+                    VirtualKeyCode::Key4, VirtualKeyCode::Key5, VirtualKeyCode::Key6,
+                    VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+                ];
+
+                if *state == ElementState::Pressed {
+                    if let Some(slot) = hotbar.iter().position(|hotbar_key| hotbar_key == key) {
+                        let mut placeable = self.registry.blocks().map(|block| block.id).filter(|id| *id != BlockState::AIR);
+                        if let Some(block) = placeable.nth(slot) {
+                            self.selected_block = block;
+                        }
+                    }
+                }
+            }
+
+            WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => {
+                // Edits are only requested here, the world changes once the server broadcasts them back
+                if let Some(hit) = raycast::pick(&self.world, &self.camera.camera, Player::REACH) {
+                    let packet = match button {
+                        MouseButton::Left => Some(ClientPacket::BreakBlock {
+                            token    : self.player_token,
+                            uuid     : self.player_uuid,
+                            position : hit.position,
+                        }),
+
+                        MouseButton::Right => {
+                            let (x, y, z) = hit.side.normal();
+                            Some(ClientPacket::PlaceBlock {
+                                token    : self.player_token,
+                                uuid     : self.player_uuid,
+                                position : hit.position + vec3(x as i32, y as i32, z as i32),
+                                block    : self.selected_block,
+                            })
+                        }
+
+                        _ => None,
+                    };
+
+                    if let Some(packet) = packet {
+                        self.transport.send(self.server, Channel::Reliable, bincode::serialize(&packet).unwrap());
+                    }
+                }
             }
 
             _ => {}
@@ -266,5 +433,6 @@ impl Screen for WorldScreen {
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.projection.resize(new_size.width, new_size.height);
         self.depth_buffer = DepthBuffer::new(&self.device, (new_size.width, new_size.height).into());
+        self.disconnect_screen.resize(new_size);
     }
 }
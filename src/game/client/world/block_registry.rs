@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::{graphics::utils::Side, resources::ResourceManager};
+
+use super::{chunk::chunk::BlockState, light::MAX_LIGHT};
+
+/// Index into `BlockRegistry::textures`, used as the texture atlas key.
+pub type TextureId = u16;
+
+/// A block as declared in a block definition file.
+/// Face textures fall back from the most specific entry to the least specific one:
+/// `right`/`left`/`front`/`back` → `side` → `all`, and `top`/`bottom` → `all`.
+#[derive(Deserialize, Debug)]
+pub struct BlockDefinition {
+    pub id           : u16,
+    pub name         : String,
+    #[serde(default)]
+    pub textures     : Option<FaceTextures>,
+    #[serde(default)]
+    pub transparency : Transparency,
+    #[serde(default = "default_true")]
+    pub solid        : bool,
+    #[serde(default)]
+    pub hardness     : f32,
+    #[serde(default)]
+    pub light        : u8,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct FaceTextures {
+    pub all    : Option<FaceTexture>,
+    pub side   : Option<FaceTexture>,
+    pub top    : Option<FaceTexture>,
+    pub bottom : Option<FaceTexture>,
+    pub right  : Option<FaceTexture>,
+    pub left   : Option<FaceTexture>,
+    pub front  : Option<FaceTexture>,
+    pub back   : Option<FaceTexture>,
+}
+
+/// Either just a texture name, or a texture with a transform: `(texture: "log", rotation: 90, flip: true)`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum FaceTexture {
+    Name(String),
+    Transformed {
+        texture  : String,
+        #[serde(default)]
+        rotation : Rotation,
+        #[serde(default)]
+        flip     : bool,
+    },
+}
+
+impl FaceTexture {
+    pub fn name(&self) -> &str {
+        return match self {
+            FaceTexture::Name(name)                  => name,
+            FaceTexture::Transformed { texture, .. } => texture,
+        };
+    }
+
+    pub fn transform(&self) -> FaceTransform {
+        return match self {
+            FaceTexture::Name(_)                               => FaceTransform::default(),
+            FaceTexture::Transformed { rotation, flip, .. } => FaceTransform { rotation: *rotation, flip: *flip },
+        };
+    }
+}
+
+/// Counter-clockwise rotation of a face's texture, written in degrees in definition files.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "u16")]
+pub enum Rotation {
+    #[default]
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        return match degrees {
+            0   => Ok(Rotation::R0),
+            90  => Ok(Rotation::R90),
+            180 => Ok(Rotation::R180),
+            270 => Ok(Rotation::R270),
+            _   => Err(format!("Texture rotation must be 0, 90, 180 or 270 degrees, got {}", degrees)),
+        };
+    }
+}
+
+/// How a block's faces are drawn and whether it hides the faces of its neighbours.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transparency {
+    /// Fully covers whatever is behind it.
+    #[default]
+    Opaque,
+    /// Texture pixels are either fully visible or not drawn at all, like leaves.
+    Cutout,
+    /// Blended with what's behind it, like glass or water. Drawn after everything else, back to front.
+    Translucent,
+}
+
+/// How a texture is oriented on a face, mirroring happens before rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FaceTransform {
+    pub rotation : Rotation,
+    pub flip     : bool,
+}
+
+impl FaceTextures {
+    pub fn get(&self, side: Side) -> Option<&FaceTexture> {
+        let specific = match side {
+            Side::Top    => &self.top,
+            Side::Bottom => &self.bottom,
+            Side::Right  => &self.right,
+            Side::Left   => &self.left,
+            Side::Front  => &self.front,
+            Side::Back   => &self.back,
+        };
+
+        let lateral = match side {
+            Side::Top | Side::Bottom => &None,
+            _                        => &self.side,
+        };
+
+        return specific.as_ref().or(lateral.as_ref()).or(self.all.as_ref());
+    }
+}
+
+fn default_true() -> bool { true }
+
+/// Resolved texture of a single block face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Face {
+    pub texture   : TextureId,
+    pub transform : FaceTransform,
+}
+
+pub struct Block {
+    pub id           : BlockState,
+    pub name         : String,
+    pub textures     : Option<[Face; 6]>, // Indexed by `Side`, `None` for invisible blocks
+    pub transparency : Transparency,
+    pub solid        : bool,
+    pub hardness     : f32, // Negative for blocks that can't be broken
+    pub light        : u8,  // Block light level it emits, up to `MAX_LIGHT`
+}
+
+/// All known blocks, indexed by their numeric id.
+/// Id 0 is always air and can't be redefined.
+pub struct BlockRegistry {
+    blocks   : Vec<Option<Block>>,
+    names    : HashMap<String, BlockState>,
+    textures : Vec<String>,
+}
+
+impl BlockRegistry {
+    /// Blocks defined in `blocks.ron` of the resource packs.
+    pub fn load(resources: &ResourceManager) -> Result<Self> {
+        return Self::from_ron(&resources.read_string("blocks.ron")?);
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self> {
+        let definitions: Vec<BlockDefinition> = ron::from_str(source)?;
+        return Self::from_definitions(definitions);
+    }
+
+    pub fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<Self> {
+        let mut registry = Self {
+            blocks   : vec![],
+            names    : HashMap::new(),
+            textures : vec![],
+        };
+
+        registry.insert(Block {
+            id           : BlockState::AIR,
+            name         : "air".into(),
+            textures     : None,
+            transparency : Transparency::Cutout, // Never drawn, it just must not hide anything
+            solid        : false,
+            hardness     : 0.0,
+            light        : 0,
+        });
+
+        for definition in definitions {
+            let id = BlockState(definition.id);
+            if registry.get(id).is_some() {
+                bail!("Block '{}' uses id {} which is already taken", definition.name, definition.id);
+            }
+
+            if registry.names.contains_key(&definition.name) {
+                bail!("Block name '{}' is defined more than once", definition.name);
+            }
+
+            if definition.light > MAX_LIGHT {
+                bail!("Block '{}' emits light level {}, the maximum is {}", definition.name, definition.light, MAX_LIGHT);
+            }
+
+            let textures = match &definition.textures {
+                Some(textures) => {
+                    let mut faces = [Face { texture: 0, transform: FaceTransform::default() }; 6];
+                    for side in Side::ALL {
+                        let texture = match textures.get(side) {
+                            Some(texture) => texture,
+                            None => bail!("Block '{}' has no texture for {:?} faces", definition.name, side),
+                        };
+
+                        faces[side as usize] = Face {
+                            texture   : registry.texture_id(texture.name()),
+                            transform : texture.transform(),
+                        };
+                    }
+
+                    Some(faces)
+                }
+
+                None => None,
+            };
+
+            registry.insert(Block {
+                id,
+                name         : definition.name,
+                textures,
+                transparency : definition.transparency,
+                solid        : definition.solid,
+                hardness     : definition.hardness,
+                light        : definition.light,
+            });
+        }
+
+        return Ok(registry);
+    }
+
+    pub fn get(&self, id: BlockState) -> Option<&Block> {
+        return self.blocks.get(id.0 as usize).and_then(Option::as_ref);
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<BlockState> {
+        return self.names.get(name).copied();
+    }
+
+    /// Unknown blocks are treated as air.
+    pub fn is_opaque(&self, id: BlockState) -> bool {
+        return self.transparency(id) == Transparency::Opaque;
+    }
+
+    pub fn transparency(&self, id: BlockState) -> Transparency {
+        return self.get(id).map_or(Transparency::Cutout, |block| block.transparency);
+    }
+
+    pub fn is_solid(&self, id: BlockState) -> bool {
+        return self.get(id).is_some_and(|block| block.solid);
+    }
+
+    /// Block light emitted, unknown blocks don't emit any.
+    pub fn light(&self, id: BlockState) -> u8 {
+        return self.get(id).map_or(0, |block| block.light);
+    }
+
+    pub fn face(&self, id: BlockState, side: Side) -> Option<Face> {
+        return self.get(id).and_then(|block| block.textures).map(|textures| textures[side as usize]);
+    }
+
+    /// Names of every texture referenced by a block, indexed by `TextureId`.
+    pub fn textures(&self) -> &[String] {
+        return &self.textures;
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        return self.blocks.iter().flatten();
+    }
+
+    fn insert(&mut self, block: Block) {
+        let index = block.id.0 as usize;
+        if self.blocks.len() <= index {
+            self.blocks.resize_with(index + 1, || None);
+        }
+
+        self.names.insert(block.name.clone(), block.id);
+        self.blocks[index] = Some(block);
+    }
+
+    fn texture_id(&mut self, name: &str) -> TextureId {
+        if let Some(id) = self.textures.iter().position(|texture| texture == name) {
+            return id as TextureId;
+        }
+
+        self.textures.push(name.to_owned());
+        return (self.textures.len() - 1) as TextureId;
+    }
+}
@@ -0,0 +1,368 @@
+use serde::{Serialize, Deserialize};
+
+use super::chunk::BlockState;
+
+/// Fixed size array of blocks, stored as a palette of distinct blocks plus bit-packed palette indices.
+/// A storage holding a single kind of block keeps just the palette and no indices at all.
+/// Index width grows when the palette overflows and shrinks once enough palette entries are unused.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawBlockStorage", into = "RawBlockStorage")]
+pub struct BlockStorage {
+    len     : usize,
+    palette : Vec<BlockState>,
+    counts  : Vec<u32>, // How many entries use each palette slot, zero for free slots
+    bits    : u32,      // Bits per index, 0 when the palette has one entry
+    data    : Vec<u64>,
+}
+
+// Serialized form, reference counts are rebuilt when loading
+#[derive(Clone, Serialize, Deserialize)]
+struct RawBlockStorage {
+    len     : usize,
+    palette : Vec<BlockState>,
+    bits    : u32,
+    data    : Vec<u64>,
+}
+
+impl BlockStorage {
+    // Widths that divide 64, so indices never straddle two words
+    const WIDTHS: [u32; 5] = [1, 2, 4, 8, 16];
+
+    pub fn new(len: usize, block_state: BlockState) -> Self {
+        return Self {
+            len,
+            palette : vec![block_state],
+            counts  : vec![len as u32],
+            bits    : 0,
+            data    : vec![],
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    pub fn bits_per_entry(&self) -> u32 {
+        return self.bits;
+    }
+
+    /// Returns the only block stored if there's just one kind.
+    pub fn single(&self) -> Option<BlockState> {
+        return if self.bits == 0 { Some(self.palette[0]) } else { None };
+    }
+
+    pub fn get(&self, index: usize) -> BlockState {
+        return self.palette[self.index(index)];
+    }
+
+    pub fn set(&mut self, index: usize, block_state: BlockState) {
+        let old = self.index(index);
+        if self.palette[old] == block_state {
+            return;
+        }
+
+        let new = match self.palette.iter().zip(&self.counts).position(|(entry, count)| *entry == block_state && *count > 0) {
+            Some(slot) => slot,
+            None => self.allocate(block_state),
+        };
+
+        self.write_index(index, new);
+        self.counts[new] += 1;
+        self.counts[old] -= 1;
+
+        if self.counts[old] == 0 {
+            self.shrink();
+        }
+    }
+
+    fn index(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+
+        let per_word = (64 / self.bits) as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        return ((self.data[index / per_word] >> shift) & mask) as usize;
+    }
+
+    fn write_index(&mut self, index: usize, value: usize) {
+        let per_word = (64 / self.bits) as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        let word = &mut self.data[index / per_word];
+        *word = (*word & !(mask << shift)) | ((value as u64) << shift);
+    }
+
+    // Finds a palette slot for a new block, widening indices if the palette is full
+    fn allocate(&mut self, block_state: BlockState) -> usize {
+        if let Some(slot) = self.counts.iter().position(|count| *count == 0) {
+            self.palette[slot] = block_state;
+            return slot;
+        }
+
+        self.palette.push(block_state);
+        self.counts.push(0);
+        if self.palette.len() > 1 << self.bits {
+            self.repack(Self::bits_for(self.palette.len()), None);
+        }
+
+        return self.palette.len() - 1;
+    }
+
+    // Drops unused palette entries once indices fit into a much narrower width, or a single block is left.
+    // Waiting for a two step drop keeps a block placed and removed at the boundary from repacking every time.
+    fn shrink(&mut self) {
+        let used = self.counts.iter().filter(|count| **count > 0).count();
+        let bits = if used <= 1 { 0 } else { Self::bits_for(used) };
+        if bits == 0 || bits * 4 <= self.bits {
+            let mut remap = vec![0; self.palette.len()];
+            let mut palette = vec![];
+            let mut counts = vec![];
+            for (slot, (entry, count)) in self.palette.iter().zip(&self.counts).enumerate() {
+                if *count > 0 {
+                    remap[slot] = palette.len();
+                    palette.push(*entry);
+                    counts.push(*count);
+                }
+            }
+
+            self.repack(bits, Some(&remap));
+            self.palette = palette;
+            self.counts = counts;
+        }
+    }
+
+    // Rewrites every index with a new width, optionally moving them to new palette slots
+    fn repack(&mut self, bits: u32, remap: Option<&[usize]>) {
+        let indices: Vec<usize> = (0 .. self.len).map(|i| {
+            let index = self.index(i);
+            remap.map_or(index, |remap| remap[index])
+        }).collect();
+
+        self.bits = bits;
+        self.data = vec![0; Self::words(self.len, bits)];
+        if bits != 0 {
+            for (i, index) in indices.into_iter().enumerate() {
+                self.write_index(i, index);
+            }
+        }
+    }
+
+    // Words needed to hold `len` indices of the given width
+    fn words(len: usize, bits: u32) -> usize {
+        return match (64usize).checked_div(bits as usize) {
+            Some(per_word) => len.div_ceil(per_word),
+            None => 0,
+        };
+    }
+
+    fn bits_for(entries: usize) -> u32 {
+        return *Self::WIDTHS.iter().find(|width| 1usize << **width >= entries).unwrap();
+    }
+}
+
+impl TryFrom<RawBlockStorage> for BlockStorage {
+    type Error = String;
+
+    fn try_from(raw: RawBlockStorage) -> Result<Self, Self::Error> {
+        let palette_fits = if raw.bits == 0 { raw.palette.len() == 1 } else { Self::WIDTHS.contains(&raw.bits) && raw.palette.len() <= 1 << raw.bits };
+        if raw.palette.is_empty() || !palette_fits || raw.data.len() != Self::words(raw.len, raw.bits) {
+            return Err("Malformed block storage".into());
+        }
+
+        let mut storage = Self {
+            len     : raw.len,
+            counts  : vec![0; raw.palette.len()],
+            palette : raw.palette,
+            bits    : raw.bits,
+            data    : raw.data,
+        };
+
+        for i in 0 .. storage.len {
+            let index = storage.index(i);
+            if index >= storage.palette.len() {
+                return Err("Block storage index out of palette bounds".into());
+            }
+
+            storage.counts[index] += 1;
+        }
+
+        return Ok(storage);
+    }
+}
+
+impl From<BlockStorage> for RawBlockStorage {
+    fn from(storage: BlockStorage) -> Self {
+        return Self {
+            len     : storage.len,
+            palette : storage.palette,
+            bits    : storage.bits,
+            data    : storage.data,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Not a multiple of any word size, so the last word is partially used
+    const LEN: usize = 1000;
+
+    fn check(storage: &BlockStorage, expected: &[BlockState]) {
+        for (i, block_state) in expected.iter().enumerate() {
+            assert_eq!(storage.get(i), *block_state, "block {} differs", i);
+        }
+
+        // Counts agree with the contents
+        for (slot, entry) in storage.palette.iter().enumerate() {
+            let count = expected.iter().filter(|block_state| *block_state == entry).count();
+            if storage.counts[slot] > 0 {
+                assert_eq!(storage.counts[slot] as usize, count);
+            }
+        }
+
+        assert_eq!(storage.counts.iter().sum::<u32>() as usize, expected.len());
+        assert_eq!(round_trip(storage), *storage);
+    }
+
+    fn round_trip(storage: &BlockStorage) -> BlockStorage {
+        return bincode::deserialize(&bincode::serialize(storage).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn widths_grow_with_the_palette() {
+        let mut storage = BlockStorage::new(LEN, BlockState(0));
+        let mut expected = vec![BlockState(0); LEN];
+        assert_eq!(storage.bits_per_entry(), 0);
+        assert_eq!(storage.single(), Some(BlockState(0)));
+
+        // Palette sizes right after each width boundary
+        let boundaries = [(2, 1), (3, 2), (5, 4), (17, 8), (257, 16)];
+        for kinds in 1 ..= 300 {
+            let index = (kinds * 3) % LEN;
+            storage.set(index, BlockState(kinds as u16));
+            expected[index] = BlockState(kinds as u16);
+
+            let used = kinds + 1;
+            let bits = boundaries.iter().rev().find(|(size, _)| used >= *size).map_or(0, |(_, bits)| *bits);
+            assert_eq!(storage.bits_per_entry(), bits, "{} kinds of blocks", used);
+            check(&storage, &expected);
+        }
+
+        assert_eq!(storage.single(), None);
+    }
+
+    #[test]
+    fn widths_shrink_once_blocks_are_gone() {
+        let mut storage = BlockStorage::new(LEN, BlockState(0));
+        let mut expected = vec![BlockState(0); LEN];
+        for kinds in 1 ..= 300 {
+            storage.set(kinds * 3, BlockState(kinds as u16));
+            expected[kinds * 3] = BlockState(kinds as u16);
+        }
+
+        assert_eq!(storage.bits_per_entry(), 16);
+
+        // Shrinking waits until indices fit into a quarter of the width
+        let mut widths = vec![];
+        for kinds in (1 ..= 300).rev() {
+            storage.set(kinds * 3, BlockState(0));
+            expected[kinds * 3] = BlockState(0);
+            check(&storage, &expected);
+
+            if widths.last() != Some(&storage.bits_per_entry()) {
+                widths.push(storage.bits_per_entry());
+            }
+
+            let used = kinds;
+            match storage.bits_per_entry() {
+                16 => assert!(used > 16),
+                4  => assert!(used <= 16 && used > 2),
+                1  => assert!(used == 2),
+                0  => assert!(used == 1),
+                bits => panic!("Unexpected width {}", bits),
+            }
+        }
+
+        assert_eq!(widths, [16, 4, 1, 0]);
+        assert_eq!(storage.single(), Some(BlockState(0)));
+        assert_eq!(storage.palette.len(), 1);
+    }
+
+    #[test]
+    fn placing_and_removing_at_a_boundary_doesnt_repack() {
+        let mut storage = BlockStorage::new(LEN, BlockState(0));
+        for kinds in 1 .. 16 {
+            storage.set(kinds, BlockState(kinds as u16));
+        }
+
+        assert_eq!(storage.bits_per_entry(), 4);
+        for _ in 0 .. 3 {
+            storage.set(0, BlockState(100));
+            assert_eq!(storage.bits_per_entry(), 8);
+            storage.set(0, BlockState(0));
+            assert_eq!(storage.bits_per_entry(), 8);
+        }
+    }
+
+    #[test]
+    fn random_edits_match_a_plain_array() {
+        let mut storage = BlockStorage::new(LEN, BlockState(7));
+        let mut expected = vec![BlockState(7); LEN];
+
+        // Small linear congruential generator, the sequence has to be the same on every run
+        let mut state = 12345u64;
+        let mut next = |range: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            return (state >> 33) % range;
+        };
+
+        for step in 0 .. 20_000 {
+            // Phases with many kinds of blocks and with only a few, so widths go up and down
+            let kinds = if (step / 2000) % 2 == 0 { 400 } else { 3 };
+            let index = next(LEN as u64) as usize;
+            let block_state = BlockState(next(kinds) as u16);
+            storage.set(index, block_state);
+            expected[index] = block_state;
+
+            if step % 500 == 0 {
+                check(&storage, &expected);
+            }
+        }
+
+        check(&storage, &expected);
+    }
+
+    #[test]
+    fn corrupted_input_is_rejected() {
+        let mut storage = BlockStorage::new(LEN, BlockState(0));
+        for i in 0 .. 10 {
+            storage.set(i, BlockState(i as u16));
+        }
+
+        let raw = RawBlockStorage::from(storage.clone());
+        let decode = |raw: RawBlockStorage| bincode::deserialize::<BlockStorage>(&bincode::serialize(&raw).unwrap());
+        assert_eq!(decode(raw.clone()).unwrap(), storage);
+
+        assert!(decode(RawBlockStorage { palette: vec![], ..raw.clone() }).is_err());
+        assert!(decode(RawBlockStorage { bits: 3, ..raw.clone() }).is_err());
+        assert!(decode(RawBlockStorage { bits: 0, data: vec![], ..raw.clone() }).is_err());
+        assert!(decode(RawBlockStorage { palette: vec![BlockState(0); 17], ..raw.clone() }).is_err());
+        assert!(decode(RawBlockStorage { data: raw.data[1 ..].to_vec(), ..raw.clone() }).is_err());
+        assert!(decode(RawBlockStorage { len: LEN + 100, ..raw.clone() }).is_err());
+
+        // Index 15 with only 10 palette entries
+        let mut data = raw.data.clone();
+        data[0] |= 0xF;
+        assert!(decode(RawBlockStorage { data, ..raw.clone() }).is_err());
+
+        let bytes = bincode::serialize(&storage).unwrap();
+        assert!(bincode::deserialize::<BlockStorage>(&bytes[.. bytes.len() - 1]).is_err());
+    }
+}
@@ -1,38 +1,104 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub enum BlockState {
-    AIR,
-    TEST,
-    PANEL,
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use serde::{Serialize, Deserialize};
+
+use super::{block_storage::BlockStorage, super::light::Light};
+
+/// Numeric block id, see `BlockRegistry` for what each id means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub struct BlockState(pub u16);
+
+impl BlockState {
+    pub const AIR: BlockState = BlockState(0);
 }
 
 pub const CHUNK_SIZE: usize = 32;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+pub const PADDED_SIZE: usize = CHUNK_SIZE + 2;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Chunk {
-    pub blocks: Vec<BlockState>,
+    pub blocks: BlockStorage,
 }
 
 impl Chunk {
     pub fn new() -> Self {
-        let mut data = vec![BlockState::AIR; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
-        for i in 0 .. CHUNK_SIZE {
-            for j in 0 .. CHUNK_SIZE / 4 {
-                for k in 0 .. CHUNK_SIZE {
-                    if k % 2 == 0 {
-                        data[Self::index_unchecked(i, j, k)] = BlockState::TEST;
-                    } else {    
-                        data[Self::index_unchecked(i, j, k)] = BlockState::PANEL;
-                    }
-
-                }
-            }
-        }
+        return Self::filled(BlockState::AIR);
+    }
 
+    pub fn filled(block_state: BlockState) -> Self {
         return Self {
-            blocks: data,
+            blocks: BlockStorage::new(CHUNK_VOLUME, block_state),
         };
     }
 
-    const fn index_unchecked(x: usize, y: usize, z: usize) -> usize {
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockState {
+        return self.blocks.get(Self::index_unchecked(x, y, z));
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block_state: BlockState) {
+        self.blocks.set(Self::index_unchecked(x, y, z), block_state);
+    }
+
+    /// Deflate-compressed bincode, the form chunks are stored on disk and sent over the network in.
+    pub fn compress(&self) -> Result<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&bincode::serialize(self)?)?;
+        return Ok(encoder.finish()?);
+    }
+
+    pub fn decompress(compressed: &[u8]) -> Result<Self> {
+        let mut bytes = vec![];
+        DeflateDecoder::new(compressed).read_to_end(&mut bytes)?;
+        return Ok(bincode::deserialize(&bytes)?);
+    }
+
+    pub const fn index_unchecked(x: usize, y: usize, z: usize) -> usize {
         return (z * CHUNK_SIZE * CHUNK_SIZE) + (y * CHUNK_SIZE) + x;
     }
 }
+
+/// Copy of a chunk surrounded by a one block thick border taken from its neighbours,
+/// so meshers can look across chunk boundaries. Valid coordinates are `-1 ..= CHUNK_SIZE`.
+pub struct PaddedChunk {
+    pub blocks : Vec<BlockState>,
+    pub light  : Vec<Light>,
+}
+
+impl PaddedChunk {
+    pub fn new() -> Self {
+        return Self {
+            blocks : vec![BlockState::AIR; PADDED_SIZE * PADDED_SIZE * PADDED_SIZE],
+            light  : vec![Light::FULL; PADDED_SIZE * PADDED_SIZE * PADDED_SIZE],
+        };
+    }
+
+    pub fn get(&self, x: isize, y: isize, z: isize) -> BlockState {
+        return self.blocks[Self::index(x, y, z)];
+    }
+
+    pub fn set(&mut self, x: isize, y: isize, z: isize, block_state: BlockState) {
+        self.blocks[Self::index(x, y, z)] = block_state;
+    }
+
+    pub fn light(&self, x: isize, y: isize, z: isize) -> Light {
+        return self.light[Self::index(x, y, z)];
+    }
+
+    pub fn set_light(&mut self, x: isize, y: isize, z: isize, light: Light) {
+        self.light[Self::index(x, y, z)] = light;
+    }
+
+    const fn index(x: isize, y: isize, z: isize) -> usize {
+        let size = PADDED_SIZE as isize;
+        return ((z + 1) * size * size + (y + 1) * size + (x + 1)) as usize;
+    }
+}
+
+impl Default for PaddedChunk {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
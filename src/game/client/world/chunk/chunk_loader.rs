@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+
+use cgmath::{Vector3, InnerSpace, vec3};
+
+use super::super::world::World;
+
+/// Keeps the chunks around a point loaded.
+/// Chunks within `radius` chunks are requested, loaded ones are only dropped once they're further away than `unload_radius`,
+/// so moving back and forth across a chunk border doesn't keep reloading the same chunks.
+pub struct ChunkLoader {
+    pub radius        : i32,
+    pub unload_radius : i32,
+
+    // Chunks that were asked for but didn't arrive yet. Requests go over a reliable channel and the server
+    // sends every chunk it was asked for, so they're never repeated. Those that went out of range are forgotten.
+    requested         : HashSet<Vector3<i32>>,
+}
+
+impl ChunkLoader {
+    // Distance between the load and unload radius
+    const HYSTERESIS: i32 = 2;
+
+    pub fn new(radius: i32) -> Self {
+        return Self {
+            radius,
+            unload_radius : radius + Self::HYSTERESIS,
+            requested     : HashSet::new(),
+        };
+    }
+
+    /// Unloads chunks that are too far from `center` and returns the missing ones that should be requested, nearest first.
+    pub fn update(&mut self, world: &mut World, center: Vector3<f32>) -> Vec<Vector3<i32>> {
+        let center = World::chunk_at(center);
+
+        let far: Vec<Vector3<i32>> = world.chunks.keys().copied()
+            .filter(|position| !Self::within(*position - center, self.unload_radius))
+            .collect();
+
+        for position in far {
+            world.remove(position);
+        }
+
+        let unload_radius = self.unload_radius;
+        self.requested.retain(|position| Self::within(*position - center, unload_radius));
+
+        let mut missing = vec![];
+        for x in -self.radius ..= self.radius {
+            for y in -self.radius ..= self.radius {
+                for z in -self.radius ..= self.radius {
+                    let offset = vec3(x, y, z);
+                    let position = center + offset;
+                    if Self::within(offset, self.radius) && world.chunk(position).is_none() && self.requested.insert(position) {
+                        missing.push(position);
+                    }
+                }
+            }
+        }
+
+        missing.sort_by_key(|position| (position - center).magnitude2());
+        return missing;
+    }
+
+    /// Call when a chunk arrives. Returns `false` if it isn't wanted anymore and should be thrown away.
+    pub fn received(&mut self, position: Vector3<i32>) -> bool {
+        return self.requested.remove(&position);
+    }
+
+    fn within(offset: Vector3<i32>, radius: i32) -> bool {
+        return offset.magnitude2() <= radius * radius;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::chunk::{Chunk, CHUNK_SIZE};
+
+    // Center of the chunk
+    fn at(position: Vector3<i32>) -> Vector3<f32> {
+        return (World::origin(position) + vec3(1, 1, 1) * CHUNK_SIZE as i32 / 2).cast().unwrap();
+    }
+
+    #[test]
+    fn chunks_are_requested_once_nearest_first() {
+        let mut world = World::new();
+        let mut loader = ChunkLoader::new(2);
+        let center = vec3(3, -1, 7);
+
+        let requested = loader.update(&mut world, at(center));
+        // Offsets with a squared length of 0, 1, 2, 3 and 4
+        assert_eq!(requested.len(), 1 + 6 + 12 + 8 + 6);
+        assert_eq!(requested[0], center);
+        assert!(requested.windows(2).all(|pair| (pair[0] - center).magnitude2() <= (pair[1] - center).magnitude2()));
+
+        // However long they take to arrive, the server is never asked twice
+        for _ in 0 .. 10 {
+            assert!(loader.update(&mut world, at(center)).is_empty());
+        }
+
+        assert!(loader.received(center));
+        world.insert(center, Chunk::new());
+        assert!(!loader.received(center));
+        assert!(loader.update(&mut world, at(center)).is_empty());
+    }
+
+    #[test]
+    fn requests_out_of_range_are_forgotten() {
+        let mut world = World::new();
+        let mut loader = ChunkLoader::new(1);
+        let start = vec3(0, 0, 0);
+        let requested = loader.update(&mut world, at(start));
+
+        // Still within the unload radius, so nothing is asked for again
+        let near = vec3(1, 0, 0);
+        assert!(loader.update(&mut world, at(near)).iter().all(|position| !requested.contains(position)));
+
+        // Chunks arriving after going out of range are thrown away, and asked for again when coming back
+        let far = vec3(10, 0, 0);
+        loader.update(&mut world, at(far));
+        assert!(!loader.received(start));
+        assert!(loader.update(&mut world, at(start)).contains(&start));
+    }
+
+    #[test]
+    fn chunks_unload_past_the_hysteresis() {
+        let mut world = World::new();
+        let mut loader = ChunkLoader::new(1);
+        for position in loader.update(&mut world, at(vec3(0, 0, 0))) {
+            assert!(loader.received(position));
+            world.insert(position, Chunk::new());
+        }
+
+        loader.update(&mut world, at(vec3(2, 0, 0)));
+        assert!(world.chunk(vec3(-1, 0, 0)).is_some());
+
+        loader.update(&mut world, at(vec3(3, 0, 0)));
+        assert!(world.chunk(vec3(-1, 0, 0)).is_none());
+        assert!(world.chunk(vec3(1, 0, 0)).is_some());
+    }
+}
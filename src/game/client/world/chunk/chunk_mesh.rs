@@ -1,160 +1,588 @@
-use cgmath::{vec3, vec2, Quaternion};
+use cgmath::{vec3, vec2, vec4, InnerSpace, Quaternion, Vector2, Vector3};
 use euclid::{Box2D, num::Zero};
+use wgpu::util::DeviceExt;
 
-use crate::graphics::{mesh::{Vertex, InstancedMesh, Instance}, utils::Side, drawable::Drawable, atlas::Atlas};
+use crate::graphics::{mesh::{Vertex, ChunkVertex, Instance}, utils::Side, drawable::Drawable};
 
-use super::chunk::{BlockState, CHUNK_SIZE};
+use super::{chunk::PaddedChunk, super::{world::World, block_registry::{BlockRegistry, TextureId, Face, FaceTransform, Rotation, Transparency}, light::{Light, MAX_LIGHT}}};
 
+/// Quads of a chunk, split by how they're drawn.
+#[derive(Default)]
+pub struct MeshData {
+    pub opaque      : Vec<ChunkVertex>, // Opaque and cutout blocks
+    pub translucent : Vec<ChunkVertex>,
+}
+
+impl MeshData {
+    pub fn get_mut(&mut self, transparency: Transparency) -> &mut Vec<ChunkVertex> {
+        return match transparency {
+            Transparency::Opaque | Transparency::Cutout => &mut self.opaque,
+            Transparency::Translucent                   => &mut self.translucent,
+        };
+    }
+}
+
+/// GPU buffers of a chunk's quads, drawn through the index buffer `ChunkRenderer` binds.
+/// Translucent quads are kept around to sort them back to front whenever the camera moves.
 pub struct ChunkMesh {
-    mesh: InstancedMesh
+        buffer             : wgpu::Buffer,
+        instance_buffer    : wgpu::Buffer,
+        translucent_buffer : Option<wgpu::Buffer>,
+        translucent        : Vec<[ChunkVertex; 4]>,
+        sorted_from        : Option<Vector3<i32>>, // Camera block of the last sort
+
+    pub origin             : Vector3<f32>,
+    pub quads              : u32,
 }
 
 impl ChunkMesh {
-    pub fn new(device: &wgpu::Device, blocks: &[BlockState], texture_atlas: &Atlas<BlockState>) -> Self {
-        let vertices = mesh::culled::<CHUNK_SIZE>(blocks, texture_atlas);
-        let mesh = InstancedMesh::new(device, vertices, vec![Instance {
-            position: (0.0, 0.0, 0.0).into(),
+    // Meshing itself happens on `ChunkMesher`'s worker threads, this only uploads the result
+    pub fn new(device: &wgpu::Device, position: Vector3<i32>, vertices: MeshData) -> Self {
+        let instance = Instance {
+            position: World::origin(position).cast::<f32>().unwrap(),
             rotation: Quaternion::zero()
-        }]);
+        };
 
         return Self {
-            mesh
+            buffer             : Self::make_buffer(device, &vertices.opaque),
+            instance_buffer    : device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label    : None,
+                contents : bytemuck::cast_slice(&[instance.to_raw()]),
+                usage    : wgpu::BufferUsages::VERTEX,
+            }),
+            translucent_buffer : Self::make_translucent_buffer(device, &vertices.translucent),
+            translucent        : bytemuck::cast_slice(&vertices.translucent).to_vec(),
+            sorted_from        : None,
+
+            origin             : instance.position,
+            quads              : (vertices.opaque.len() / 4) as u32,
         };
     }
 
-    pub fn rebuild(&mut self, device: &wgpu::Device, blocks: &[BlockState], texture_atlas: &Atlas<BlockState>) {
-        self.mesh.vertices = mesh::culled::<CHUNK_SIZE>(blocks, texture_atlas);
-        self.mesh.bake(device);
+    pub fn upload(&mut self, device: &wgpu::Device, vertices: MeshData) {
+        self.buffer = Self::make_buffer(device, &vertices.opaque);
+        self.quads = (vertices.opaque.len() / 4) as u32;
+
+        self.translucent_buffer = Self::make_translucent_buffer(device, &vertices.translucent);
+        self.translucent = bytemuck::cast_slice(&vertices.translucent).to_vec();
+        self.sorted_from = None;
+    }
+
+    pub fn has_translucent(&self) -> bool {
+        return !self.translucent.is_empty();
+    }
+
+    /// Orders the translucent quads back to front as seen from `eye`.
+    /// That only changes noticeably once the camera enters another block, so sorting is skipped until then.
+    pub fn sort_translucent(&mut self, queue: &wgpu::Queue, eye: Vector3<f32>) {
+        let block = eye.map(|coordinate| coordinate.floor() as i32);
+        let buffer = match &self.translucent_buffer {
+            Some(buffer) if self.sorted_from != Some(block) => buffer,
+            _ => return,
+        };
+
+        // Positions are in whole blocks, so four times the center is exact
+        let eye = (eye - self.origin) * 4.0;
+        let mut quads: Vec<_> = self.translucent.iter().map(|quad| {
+            let center = quad.iter().map(|vertex| vertex.position()).fold(vec3(0, 0, 0), |sum, position| sum + position);
+            return ((center.cast::<f32>().unwrap() - eye).magnitude2(), *quad);
+        }).collect();
+
+        quads.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        self.translucent = quads.into_iter().map(|(_, quad)| quad).collect();
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&self.translucent));
+        self.sorted_from = Some(block);
+    }
+
+    pub fn draw_translucent<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(buffer) = &self.translucent_buffer {
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.draw_indexed(0 .. self.translucent.len() as u32 * 6, 0, 0 .. 1);
+        }
+    }
+
+    fn make_translucent_buffer(device: &wgpu::Device, vertices: &[ChunkVertex]) -> Option<wgpu::Buffer> {
+        return if vertices.is_empty() { None } else { Some(Self::make_buffer(device, vertices)) };
+    }
+
+    fn make_buffer(device: &wgpu::Device, vertices: &[ChunkVertex]) -> wgpu::Buffer {
+        return device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label    : None,
+            contents : bytemuck::cast_slice(vertices),
+            usage    : wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
     }
 }
 
 impl Drawable for ChunkMesh {
     fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
-        self.mesh.draw(render_pass);
+        if self.quads > 0 {
+            render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.draw_indexed(0 .. self.quads * 6, 0, 0 .. 1);
+        }
+    }
+}
+
+/// Ambient occlusion of an unobstructed vertex.
+pub const MAX_AO: u8 = 3;
+
+#[allow(clippy::too_many_arguments)]
+pub fn block_face(side: Side, i: isize, j: isize, k: isize, uv: Box2D<f32, f32>, transform: FaceTransform, light: Light, ao: [u8; 4]) -> [Vertex; 6] {
+    return quad_face(side, i, j, k, 1, 1, uv, transform, light, ao);
+}
+
+/// Ambient occlusion of a face vertex from the two blocks along its edges and the one at its corner,
+/// from 0 (fully occluded) to `MAX_AO`. Both edges being blocked hides the corner, whatever is there.
+pub const fn vertex_ao(side1: bool, side2: bool, corner: bool) -> u8 {
+    if side1 && side2 {
+        return 0;
     }
+
+    return MAX_AO - side1 as u8 - side2 as u8 - corner as u8;
 }
 
-pub const fn block_face(side: Side, i: isize, j: isize, k: isize, uv: Box2D<f32, f32>) -> [Vertex; 6] {
-    let (ox, oy) = (uv.min.x, uv.min.y);
-    let (sx, sy) = (uv.max.x, uv.max.y);
-    return match side {
-        Side::Top => [
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(ox, oy) },
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(sx, oy) },
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(ox, sy) },
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(ox, oy) },
-        ],
-        Side::Bottom => [
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(ox, oy) },
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(ox, sy) },
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(sx, oy) },
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(ox, oy) },
-        ],
-        Side::Right => [
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(ox, oy) },
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(sx, oy) },
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(ox, sy) },
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(ox, oy) },
-        ],
-        Side::Left => [
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(sx, oy) },
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(sx, sy) },
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(ox, sy) },
-            
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(ox, sy) },
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(ox, oy) },
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(sx, oy) },
-        ],
-        Side::Front => [
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(sx, oy) },
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(sx, sy) },
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(ox, sy) },
-            
-            Vertex { pos: vec3((1 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(ox, sy) },
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(ox, oy) },
-            Vertex { pos: vec3((1 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(sx, oy) },
-        ],
-        Side::Back => [
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(ox, oy) },
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (1 + k) as f32), uv: vec2(sx, oy) },
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (1 + k) as f32), uv: vec2(sx, sy) },
-            Vertex { pos: vec3((0 + i) as f32, (1 + j) as f32, (0 + k) as f32), uv: vec2(ox, sy) },
-            Vertex { pos: vec3((0 + i) as f32, (0 + j) as f32, (0 + k) as f32), uv: vec2(ox, oy) },
-        ],
+/// Builds a `w` by `h` blocks quad on the given side of block `(i, j, k)`.
+/// `w` spans the face's U axis (Z for top/bottom and front/back, X for right/left),
+/// `h` spans its V axis (X for top/bottom, Y otherwise).
+/// The texture is repeated once per block, see `core.wgsl`. `light` is the light of the block in front of the face,
+/// `ao` the ambient occlusion of the (0, 0), (w, 0), (w, h) and (0, h) corners.
+#[allow(clippy::too_many_arguments)]
+pub fn quad_face(side: Side, i: isize, j: isize, k: isize, w: isize, h: isize, uv: Box2D<f32, f32>, transform: FaceTransform, light: Light, ao: [u8; 4]) -> [Vertex; 6] {
+    let tile = vec4(uv.min.x, uv.min.y, uv.max.x, uv.max.y);
+    let light = vec2(light.sky as f32, light.block as f32) / MAX_LIGHT as f32;
+    let [a, b, c, d] = quad_corners(side, i, j, k, w, h, transform, ao).map(|(pos, uv, corner)| Vertex {
+        pos : pos.cast::<f32>().unwrap(),
+        uv  : uv.cast::<f32>().unwrap(),
+        tile,
+        light,
+        ao  : ao[corner] as f32 / MAX_AO as f32,
+    });
+
+    return [a, b, c, c, d, a];
+}
+
+/// Same quad as `quad_face` in the packed chunk format, four vertices drawn as two indexed triangles.
+#[allow(clippy::too_many_arguments)]
+pub fn chunk_quad(side: Side, i: isize, j: isize, k: isize, w: isize, h: isize, texture: TextureId, transform: FaceTransform, light: Light, ao: [u8; 4]) -> [ChunkVertex; 4] {
+    return quad_corners(side, i, j, k, w, h, transform, ao).map(|(pos, uv, corner)| {
+        ChunkVertex::new(pos.cast::<u32>().unwrap(), side, uv.cast::<i32>().unwrap(), texture, [light.sky, light.block], ao[corner])
+    });
+}
+
+// Corners of a quad as position, texture coordinates and index into `ao`, in the order they're drawn in:
+// triangles (0, 1, 2) and (2, 3, 0)
+#[allow(clippy::too_many_arguments)]
+fn quad_corners(side: Side, i: isize, j: isize, k: isize, w: isize, h: isize, transform: FaceTransform, ao: [u8; 4]) -> [(Vector3<isize>, Vector2<isize>, usize); 4] {
+    let x = vec3(1, 0, 0);
+    let y = vec3(0, 1, 0);
+    let z = vec3(0, 0, 1);
+
+    // Position of the quad's (0, 0) corner and the directions of its U and V edges
+    let (origin, u_axis, v_axis) = match side {
+        Side::Top    => (vec3(i, j + 1, k), z, x),
+        Side::Bottom => (vec3(i, j, k),     z, x),
+        Side::Right  => (vec3(i, j, k + 1), x, y),
+        Side::Left   => (vec3(i, j, k),     x, y),
+        Side::Front  => (vec3(i + 1, j, k), z, y),
+        Side::Back   => (vec3(i, j, k),     z, y),
+    };
+
+    // Corner order keeps the triangles counter-clockwise when looking at the face from outside
+    let corners = match side {
+        Side::Top | Side::Right | Side::Back => [0, 1, 2, 3],
+        _                                    => [0, 3, 2, 1],
+    };
+
+    // Left and front faces are seen from the opposite direction, so mirror U to keep textures upright
+    let flip = matches!(side, Side::Left | Side::Front) != transform.flip;
+
+    // Texture coordinates only matter modulo 1.0, so mirroring and rotating around the origin is enough
+    let corner = |corner: usize| {
+        let (u, v) = [(0, 0), (w, 0), (w, h), (0, h)][corner];
+        let s = if flip { -u } else { u };
+        let uv = match transform.rotation {
+            Rotation::R0   => vec2( s,  v),
+            Rotation::R90  => vec2( v, -s),
+            Rotation::R180 => vec2(-s, -v),
+            Rotation::R270 => vec2(-v,  s),
+        };
+
+        return (origin + u_axis * u + v_axis * v, uv, corner);
     };
+
+    // Split along the darker diagonal, otherwise the occlusion gradient looks different depending on the face's orientation
+    let [a, b, c, d] = corners;
+    if ao[a] + ao[c] > ao[b] + ao[d] {
+        return [corner(b), corner(c), corner(d), corner(a)];
+    }
+
+    return [corner(a), corner(b), corner(c), corner(d)];
 }
 
 #[allow(dead_code)]
-mod mesh {
+pub mod mesh {
     use super::*;
 
     // Meshing algorithms
     // Creates 6 faces for each voxel
-    pub fn simple<const CHUNK_SIZE: usize>(data: &[BlockState], texture_atlas: &Atlas<BlockState>) -> Vec<Vertex> {
-        let mut vertices = vec![];
+    pub fn simple<const CHUNK_SIZE: usize>(data: &PaddedChunk, registry: &BlockRegistry) -> MeshData {
+        let mut vertices = MeshData::default();
         for i in 0 .. CHUNK_SIZE as isize {
             for j in 0 .. CHUNK_SIZE as isize {
                 for k in 0 .. CHUNK_SIZE as isize {
-                    let block_state = data[index::<CHUNK_SIZE>(i, j, k)];
-                    if block_state != BlockState::AIR {
-                        let uv = texture_atlas.uv(&block_state);
-                        vertices.extend(block_face(Side::Top,    i, j, k, uv));
-                        vertices.extend(block_face(Side::Bottom, i, j, k, uv));
-                        vertices.extend(block_face(Side::Right,  i, j, k, uv));
-                        vertices.extend(block_face(Side::Left,   i, j, k, uv));
-                        vertices.extend(block_face(Side::Front,  i, j, k, uv));
-                        vertices.extend(block_face(Side::Back,   i, j, k, uv));
+                    for side in Side::ALL {
+                        if let Some(face) = registry.face(data.get(i, j, k), side) {
+                            let light = face_light(data, side, i, j, k);
+                            let ao = face_ao(data, registry, side, i, j, k);
+                            let quad = chunk_quad(side, i, j, k, 1, 1, face.texture, face.transform, light, ao);
+                            vertices.get_mut(registry.transparency(data.get(i, j, k))).extend(quad);
+                        }
                     }
                 }
 
             }
 
         }
-        
+
         return vertices;
     }
 
-    // Creates only the faces visible from outside
-    pub fn culled<const CHUNK_SIZE: usize>(data: &[BlockState], texture_atlas: &Atlas<BlockState>) -> Vec<Vertex> {
-        let mut vertices = vec![];
+    // Creates only the faces visible from outside, including faces on the chunk border
+    // that are hidden by blocks of the neighbouring chunks
+    pub fn culled<const CHUNK_SIZE: usize>(data: &PaddedChunk, registry: &BlockRegistry) -> MeshData {
+        let mut vertices = MeshData::default();
         for i in 0 .. CHUNK_SIZE as isize {
             for j in 0 .. CHUNK_SIZE as isize {
                 for k in 0 .. CHUNK_SIZE as isize {
-                    let block_state = data[index::<CHUNK_SIZE>(i, j, k)];
-                    if block_state != BlockState::AIR {
-                        let uv = texture_atlas.uv(&block_state);
-                        let chunk_volume: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
-                        let v = index::<CHUNK_SIZE>(i, j + 1, k); if v != chunk_volume { if unsafe { *data.get_unchecked(v) == BlockState::AIR } { vertices.extend(block_face(Side::Top,    i, j, k, uv)); } } else { vertices.extend(block_face(Side::Top,    i, j, k, uv)); }
-                        let v = index::<CHUNK_SIZE>(i, j - 1, k); if v != chunk_volume { if unsafe { *data.get_unchecked(v) == BlockState::AIR } { vertices.extend(block_face(Side::Bottom, i, j, k, uv)); } } else { vertices.extend(block_face(Side::Bottom, i, j, k, uv)); }
-                        let v = index::<CHUNK_SIZE>(i, j, k + 1); if v != chunk_volume { if unsafe { *data.get_unchecked(v) == BlockState::AIR } { vertices.extend(block_face(Side::Right,  i, j, k, uv)); } } else { vertices.extend(block_face(Side::Right,  i, j, k, uv)); }
-                        let v = index::<CHUNK_SIZE>(i, j, k - 1); if v != chunk_volume { if unsafe { *data.get_unchecked(v) == BlockState::AIR } { vertices.extend(block_face(Side::Left,   i, j, k, uv)); } } else { vertices.extend(block_face(Side::Left,   i, j, k, uv)); }
-                        let v = index::<CHUNK_SIZE>(i + 1, j, k); if v != chunk_volume { if unsafe { *data.get_unchecked(v) == BlockState::AIR } { vertices.extend(block_face(Side::Front,  i, j, k, uv)); } } else { vertices.extend(block_face(Side::Front,  i, j, k, uv)); }
-                        let v = index::<CHUNK_SIZE>(i - 1, j, k); if v != chunk_volume { if unsafe { *data.get_unchecked(v) == BlockState::AIR } { vertices.extend(block_face(Side::Back,   i, j, k, uv)); } } else { vertices.extend(block_face(Side::Back,   i, j, k, uv)); }
+                    for side in Side::ALL {
+                        if let Some(face) = visible_face(data, registry, side, i, j, k) {
+                            let light = face_light(data, side, i, j, k);
+                            let ao = face_ao(data, registry, side, i, j, k);
+                            let quad = chunk_quad(side, i, j, k, 1, 1, face.texture, face.transform, light, ao);
+                            vertices.get_mut(registry.transparency(data.get(i, j, k))).extend(quad);
+                        }
                     }
                 }
 
             }
 
         }
-        
+
         return vertices;
     }
 
-    const fn index<const CHUNK_SIZE: usize>(x: isize, y: isize, z: isize) -> usize {
-        let chunk_size = CHUNK_SIZE as isize;
-        let chunk_volume = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as isize;
-        return if x < 0 || y < 0 || z < 0 || x >= chunk_size || y >= chunk_size || z >= chunk_size { chunk_volume as usize }
-        else { ((z * chunk_size * chunk_size) + (y * chunk_size) + x) as usize };
+    // Same faces as `culled`, but coplanar neighbouring faces with the same texture, transform, transparency, light and occlusion
+    // are merged into larger quads
+    pub fn greedy<const CHUNK_SIZE: usize>(data: &PaddedChunk, registry: &BlockRegistry) -> MeshData {
+        let size = CHUNK_SIZE as isize;
+        let mut vertices = MeshData::default();
+        let mut mask = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+        for side in Side::ALL {
+            for layer in 0 .. size {
+                // Collect visible faces of this layer, indexed by (u, v)
+                for v in 0 .. size {
+                    for u in 0 .. size {
+                        let (i, j, k) = face_block(side, layer, u, v);
+                        let transparency = registry.transparency(data.get(i, j, k));
+                        mask[(v * size + u) as usize] = visible_face(data, registry, side, i, j, k)
+                            .map(|face| (face, transparency, face_light(data, side, i, j, k), face_ao(data, registry, side, i, j, k)));
+                    }
+                }
+
+                // Grow each face along U first, then along V for as long as whole rows match
+                for v in 0 .. size {
+                    let mut u = 0;
+                    while u < size {
+                        let (face, transparency, light, ao) = match mask[(v * size + u) as usize] {
+                            Some(entry) => entry,
+                            None => { u += 1; continue; }
+                        };
+
+                        let mut w = 1;
+                        while u + w < size && mask[(v * size + u + w) as usize] == Some((face, transparency, light, ao)) {
+                            w += 1;
+                        }
+
+                        let mut h = 1;
+                        while v + h < size && (u .. u + w).all(|du| mask[((v + h) * size + du) as usize] == Some((face, transparency, light, ao))) {
+                            h += 1;
+                        }
+
+                        for dv in v .. v + h {
+                            for du in u .. u + w {
+                                mask[(dv * size + du) as usize] = None;
+                            }
+                        }
+
+                        let (i, j, k) = face_block(side, layer, u, v);
+                        vertices.get_mut(transparency).extend(chunk_quad(side, i, j, k, w, h, face.texture, face.transform, light, ao));
+                        u += w;
+                    }
+                }
+            }
+        }
+
+        return vertices;
+    }
+
+    // Texture of the block's face if it isn't hidden behind an opaque neighbour
+    fn visible_face(data: &PaddedChunk, registry: &BlockRegistry, side: Side, i: isize, j: isize, k: isize) -> Option<Face> {
+        let (dx, dy, dz) = side.normal();
+        let block = data.get(i, j, k);
+        let neighbour = data.get(i + dx, j + dy, k + dz);
+        if registry.is_opaque(neighbour) {
+            return None;
+        }
+
+        // Nobody wants to see the inside of a body of water or the seams of a glass wall
+        if neighbour == block && registry.transparency(block) == Transparency::Translucent {
+            return None;
+        }
+
+        return registry.face(block, side);
+    }
+
+    // Light falling onto the face, which is the light of the block in front of it
+    fn face_light(data: &PaddedChunk, side: Side, i: isize, j: isize, k: isize) -> Light {
+        let (dx, dy, dz) = side.normal();
+        return data.light(i + dx, j + dy, k + dz);
     }
-}
\ No newline at end of file
+
+    // Ambient occlusion of the face's corners, in `quad_face` order, from the blocks around the one in front of it
+    fn face_ao(data: &PaddedChunk, registry: &BlockRegistry, side: Side, i: isize, j: isize, k: isize) -> [u8; 4] {
+        let (dx, dy, dz) = side.normal();
+        let front = vec3(i + dx, j + dy, k + dz);
+        let (u_axis, v_axis) = match side {
+            Side::Top   | Side::Bottom => (vec3(0, 0, 1), vec3(1, 0, 0)),
+            Side::Right | Side::Left   => (vec3(1, 0, 0), vec3(0, 1, 0)),
+            Side::Front | Side::Back   => (vec3(0, 0, 1), vec3(0, 1, 0)),
+        };
+
+        let opaque = |offset: Vector3<isize>| {
+            let position = front + offset;
+            return registry.is_opaque(data.get(position.x, position.y, position.z));
+        };
+
+        return [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(u, v)| {
+            let (u, v) = (u_axis * u, v_axis * v);
+            return vertex_ao(opaque(u), opaque(v), opaque(u + v));
+        });
+    }
+
+    // Maps a layer along the side's normal and (u, v) coordinates inside of it to a block position,
+    // using the same U and V axes as `quad_face`
+    const fn face_block(side: Side, layer: isize, u: isize, v: isize) -> (isize, isize, isize) {
+        return match side {
+            Side::Top   | Side::Bottom => (v, layer, u),
+            Side::Right | Side::Left   => (u, v, layer),
+            Side::Front | Side::Back   => (layer, v, u),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+    use super::super::chunk::{BlockState, CHUNK_SIZE};
+
+    const TEST: BlockState = BlockState(1);
+    const PANEL: BlockState = BlockState(2);
+    const GLASS: BlockState = BlockState(3);
+
+    fn registry() -> BlockRegistry {
+        return BlockRegistry::from_ron(r#"#![enable(implicit_some)] [
+            (id: 1, name: "test",  textures: (all: "test")),
+            (id: 2, name: "panel", textures: (all: "panel")),
+            (id: 3, name: "glass", textures: (all: "glass"), transparency: Translucent),
+        ]"#).unwrap();
+    }
+
+    fn filled(block: impl Fn(isize, isize, isize) -> BlockState) -> PaddedChunk {
+        let mut chunk = PaddedChunk::new();
+        let size = CHUNK_SIZE as isize;
+        for i in 0 .. size {
+            for j in 0 .. size {
+                for k in 0 .. size {
+                    chunk.set(i, j, k, block(i, j, k));
+                }
+            }
+        }
+
+        return chunk;
+    }
+
+    // Total area of the quads, counted per side so faces can't make up for each other
+    fn area(vertices: &[ChunkVertex]) -> [i32; 6] {
+        let mut area = [0; 6];
+        for quad in vertices.chunks(4) {
+            let [a, b, _, d] = [quad[0], quad[1], quad[2], quad[3]].map(|vertex| vertex.position().cast::<i32>().unwrap());
+            let cross: Vector3<i32> = (b - a).cross(d - a);
+            let side = (quad[0].data[0] >> 18 & 7) as usize;
+            area[side] += cross.x.abs() + cross.y.abs() + cross.z.abs();
+        }
+
+        return area;
+    }
+
+    fn compare(chunk: &PaddedChunk) -> (usize, usize) {
+        let registry = registry();
+        let culled = mesh::culled::<CHUNK_SIZE>(chunk, &registry);
+        let greedy = mesh::greedy::<CHUNK_SIZE>(chunk, &registry);
+
+        assert_eq!(area(&culled.opaque), area(&greedy.opaque));
+        assert_eq!(area(&culled.translucent), area(&greedy.translucent));
+        return (culled.opaque.len() + culled.translucent.len(), greedy.opaque.len() + greedy.translucent.len());
+    }
+
+    #[test]
+    fn solid_chunk() {
+        let (culled, greedy) = compare(&filled(|_, _, _| TEST));
+        assert_eq!(culled, 6 * CHUNK_SIZE * CHUNK_SIZE * 4);
+        assert_eq!(greedy, 6 * 4);
+    }
+
+    #[test]
+    fn checkerboard() {
+        // Nothing to merge, every face is on its own
+        let (culled, greedy) = compare(&filled(|i, j, k| if (i + j + k) % 2 == 0 { TEST } else { BlockState::AIR }));
+        assert_eq!(culled, greedy);
+    }
+
+    #[test]
+    fn mixed_textures_and_light() {
+        let mut chunk = filled(|i, j, k| match (i + 2 * j + 3 * k) % 7 {
+            0     => PANEL,
+            1     => BlockState::AIR,
+            2 | 3 => GLASS,
+            _     => TEST,
+        });
+
+        for i in -1 ..= CHUNK_SIZE as isize {
+            for k in -1 ..= CHUNK_SIZE as isize {
+                chunk.set_light(i, CHUNK_SIZE as isize, k, Light { sky: (i % 16) as u8, block: (k % 3) as u8 });
+            }
+        }
+
+        let (culled, greedy) = compare(&chunk);
+        assert!(greedy < culled);
+    }
+
+    #[test]
+    fn terrain_with_holes() {
+        let (culled, greedy) = compare(&filled(|i, j, k| match j {
+            _ if (i - 16).pow(2) + (k - 16).pow(2) < 40 => BlockState::AIR,
+            0 ..= 11  => PANEL,
+            12 ..= 20 => TEST,
+            _         => BlockState::AIR,
+        }));
+
+        assert!(greedy < culled);
+    }
+
+    #[test]
+    fn vertex_ao_corner_configurations() {
+        assert_eq!(vertex_ao(false, false, false), MAX_AO);
+        assert_eq!(vertex_ao(false, false, true), 2);
+        assert_eq!(vertex_ao(true, false, false), 2);
+        assert_eq!(vertex_ao(false, true, false), 2);
+        assert_eq!(vertex_ao(true, false, true), 1);
+        assert_eq!(vertex_ao(false, true, true), 1);
+
+        // Both sides hide the corner
+        assert_eq!(vertex_ao(true, true, false), 0);
+        assert_eq!(vertex_ao(true, true, true), 0);
+    }
+
+    // Occlusion of the top face of block (5, 5, 5) with the given blocks around it, by vertex position
+    fn top_face_ao(blocks: &[(isize, isize, isize)]) -> Vec<((u32, u32, u32), u8)> {
+        let mut chunk = PaddedChunk::new();
+        chunk.set(5, 5, 5, TEST);
+        for (i, j, k) in blocks {
+            chunk.set(*i, *j, *k, TEST);
+        }
+
+        let vertices = mesh::culled::<CHUNK_SIZE>(&chunk, &registry()).opaque;
+        let quad = vertices.chunks(4).find(|quad| {
+            let position = quad[0].position();
+            return quad[0].data[0] >> 18 & 7 == Side::Top as u32 && position.y == 6 && (5 ..= 6).contains(&position.x) && (5 ..= 6).contains(&position.z);
+        }).unwrap();
+
+        let mut ao: Vec<_> = quad.iter().map(|vertex| {
+            let position = vertex.position();
+            return ((position.x, position.y, position.z), (vertex.data[0] >> 21 & 3) as u8);
+        }).collect();
+
+        ao.sort();
+        return ao;
+    }
+
+    #[test]
+    fn face_ao_from_neighbours() {
+        let corners = [(5, 6, 5), (5, 6, 6), (6, 6, 5), (6, 6, 6)];
+        let expect = |ao: [u8; 4]| corners.iter().copied().zip(ao).collect::<Vec<_>>();
+
+        assert_eq!(top_face_ao(&[]), expect([3, 3, 3, 3]));
+
+        // Blocks below the face's plane don't matter
+        assert_eq!(top_face_ao(&[(4, 5, 5), (4, 5, 4)]), expect([3, 3, 3, 3]));
+
+        // Corner only
+        assert_eq!(top_face_ao(&[(4, 6, 4)]), expect([2, 3, 3, 3]));
+
+        // One side and the corner, the side touches two vertices
+        assert_eq!(top_face_ao(&[(4, 6, 5), (4, 6, 4)]), expect([1, 2, 3, 3]));
+
+        // Two sides give full occlusion whatever is in the corner
+        assert_eq!(top_face_ao(&[(4, 6, 5), (5, 6, 4)]), expect([0, 2, 2, 3]));
+        assert_eq!(top_face_ao(&[(4, 6, 5), (5, 6, 4), (4, 6, 4)]), expect([0, 2, 2, 3]));
+
+        // Surrounded on every side
+        let ring = [(4, 6, 4), (4, 6, 5), (4, 6, 6), (5, 6, 4), (5, 6, 6), (6, 6, 4), (6, 6, 5), (6, 6, 6)];
+        assert_eq!(top_face_ao(&ring), expect([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn quads_split_along_the_darker_diagonal() {
+        let ao_of = |quad: [ChunkVertex; 4]| quad.map(|vertex| (vertex.data[0] >> 21 & 3) as u8);
+        for side in Side::ALL {
+            for ao in [[3, 0, 3, 0], [0, 3, 0, 3], [3, 3, 0, 3], [2, 1, 2, 2], [3, 3, 3, 3], [1, 2, 3, 0]] {
+                let quad = chunk_quad(side, 1, 1, 1, 1, 1, 0, FaceTransform::default(), Light::FULL, ao);
+
+                // Triangles (0, 1, 2) and (2, 3, 0) share the diagonal from vertex 0 to vertex 2
+                let drawn = ao_of(quad);
+                assert!(drawn[0] + drawn[2] <= drawn[1] + drawn[3], "{:?} with occlusion {:?} split as {:?}", side, ao, drawn);
+
+                // Same corners either way
+                let mut drawn = drawn.to_vec();
+                let mut ao = ao.to_vec();
+                drawn.sort();
+                ao.sort();
+                assert_eq!(drawn, ao);
+            }
+        }
+    }
+
+    #[test]
+    fn builtin_blocks_split_by_transparency() {
+        let registry = BlockRegistry::load(&crate::resources::ResourceManager::new()).unwrap();
+        let block = |name: &str| registry.by_name(name).unwrap();
+        assert_eq!(registry.transparency(block("leaves")), Transparency::Cutout);
+        assert_eq!(registry.transparency(block("glass")), Transparency::Translucent);
+        assert_eq!(registry.transparency(block("water")), Transparency::Translucent);
+
+        // Leaves go with the opaque quads, glass and water get blended; touching water hides the faces in between
+        let mut chunk = PaddedChunk::new();
+        chunk.set(1, 1, 1, block("leaves"));
+        chunk.set(5, 1, 1, block("glass"));
+        chunk.set(9, 1, 1, block("water"));
+        chunk.set(10, 1, 1, block("water"));
+
+        let mesh = mesh::culled::<CHUNK_SIZE>(&chunk, &registry);
+        assert_eq!(mesh.opaque.len(), 6 * 4);
+        assert_eq!(mesh.translucent.len(), (6 + 10) * 4);
+    }
+}
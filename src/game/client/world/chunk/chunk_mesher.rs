@@ -0,0 +1,116 @@
+use std::sync::{Arc, mpsc::{self, Sender, Receiver}};
+
+use cgmath::Vector3;
+
+use super::{chunk::{PaddedChunk, CHUNK_SIZE}, chunk_mesh::{mesh, MeshData}, super::block_registry::BlockRegistry};
+
+pub struct MeshJob {
+    pub position : Vector3<i32>,
+    pub version  : u64,
+    pub blocks   : PaddedChunk,
+}
+
+pub struct MeshResult {
+    pub position : Vector3<i32>,
+    pub version  : u64,
+    pub vertices : MeshData,
+}
+
+/// Runs meshing jobs on a pool of worker threads and hands finished vertex data back through a channel.
+/// There are no threads on wasm, so jobs are meshed right away when submitted.
+pub struct ChunkMesher {
+    results       : Receiver<MeshResult>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs          : Option<Sender<MeshJob>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    workers       : Vec<std::thread::JoinHandle<()>>,
+
+    #[cfg(target_arch = "wasm32")]
+    result_sender : Sender<MeshResult>,
+    #[cfg(target_arch = "wasm32")]
+    registry      : Arc<BlockRegistry>,
+}
+
+impl ChunkMesher {
+    pub fn new(registry: Arc<BlockRegistry>) -> Self {
+        let (result_sender, results) = mpsc::channel::<MeshResult>();
+
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                return Self {
+                    results,
+                    result_sender,
+                    registry,
+                };
+            } else {
+                let (jobs, job_receiver) = mpsc::channel::<MeshJob>();
+                let job_receiver = Arc::new(std::sync::Mutex::new(job_receiver));
+
+                // Leave one core for the render thread
+                let count = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2).saturating_sub(1).max(1);
+                let workers = (0 .. count).map(|i| {
+                    let job_receiver = job_receiver.clone();
+                    let result_sender = result_sender.clone();
+                    let registry = registry.clone();
+
+                    std::thread::Builder::new().name(format!("chunk-mesher-{}", i)).spawn(move || {
+                        loop {
+                            // The lock is only held while waiting for a job, not while meshing it
+                            let job = match job_receiver.lock().unwrap().recv() {
+                                Ok(job) => job,
+                                Err(_) => break, // ChunkMesher was dropped
+                            };
+
+                            if result_sender.send(Self::process(job, &registry)).is_err() {
+                                break;
+                            }
+                        }
+                    }).unwrap()
+                }).collect();
+
+                return Self {
+                    results,
+                    jobs: Some(jobs),
+                    workers,
+                };
+            }
+        }
+    }
+
+    pub fn submit(&mut self, job: MeshJob) {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                self.result_sender.send(Self::process(job, &self.registry)).unwrap();
+            } else {
+                if let Some(jobs) = &self.jobs {
+                    jobs.send(job).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Returns a finished mesh if there is one, never blocks.
+    pub fn poll(&mut self) -> Option<MeshResult> {
+        return self.results.try_recv().ok();
+    }
+
+    fn process(job: MeshJob, registry: &BlockRegistry) -> MeshResult {
+        return MeshResult {
+            position : job.position,
+            version  : job.version,
+            vertices : mesh::greedy::<CHUNK_SIZE>(&job.blocks, registry),
+        };
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for ChunkMesher {
+    fn drop(&mut self) {
+        // Closing the job channel stops the workers
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
@@ -1,31 +1,226 @@
-use crate::graphics::{drawable::Drawable, bindable::Bindable, atlas::Atlas};
+use std::{collections::HashMap, sync::Arc};
 
-use super::{chunk_mesh::ChunkMesh, chunk::{Chunk, BlockState}};
+use anyhow::{Result, bail};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3, vec3};
+
+use crate::{graphics::{drawable::Drawable, bindable::Bindable, atlas::Atlas, frustum::Frustum, mesh::QuadIndices, uniform::Uniform, utils}, resources::ResourceManager};
+
+use super::{chunk::CHUNK_SIZE, chunk_mesh::ChunkMesh, chunk_mesher::{ChunkMesher, MeshJob}, super::{world::World, block_registry::{BlockRegistry, TextureId}}};
+
+/// Chunk mesh counts of the last `ChunkRenderer::cull`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChunkRenderStats {
+    pub drawn  : usize,
+    pub culled : usize,
+}
+
+/// Atlas page and region of a texture.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct Tile {
+    pub rect     : [f32; 4], // min.x, min.y, max.x, max.y
+    pub page     : u32,
+        _padding : [u32; 3], // Uniform array elements are 16 byte aligned
+}
+
+/// Atlas location of every texture, looked up by `chunk.wgsl` with the texture index of a vertex.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TileTable {
+    pub tiles: [Tile; TileTable::MAX_TILES],
+}
+
+impl TileTable {
+    pub const MAX_TILES: usize = 256; // Must match the array size in `chunk.wgsl`
+}
+
+impl Default for TileTable {
+    fn default() -> Self {
+        return Self { tiles: [Tile::default(); Self::MAX_TILES] };
+    }
+}
+
+unsafe impl Pod for TileTable {}
+unsafe impl Zeroable for TileTable {}
 
 pub struct ChunkRenderer {
-    pub texture_atlas : Atlas<BlockState>,
-    pub chunk_meshes  : Vec<ChunkMesh>,
+    pub texture_atlas : Atlas<TextureId>,
+    pub chunk_meshes  : HashMap<Vector3<i32>, ChunkMesh>,
+    pub mesher        : ChunkMesher,
+    pub stats         : ChunkRenderStats,
+
+    pipeline          : wgpu::RenderPipeline,
+    translucent       : wgpu::RenderPipeline,
+    tile_table        : Uniform<TileTable>,
+    indices           : QuadIndices,
+
+    // Latest job version per chunk, results of older jobs are thrown away
+    pending           : HashMap<Vector3<i32>, u64>,
+    next_version      : u64,
+
+    // Chunks that passed the last `cull`, the only ones drawn
+    visible           : Vec<Vector3<i32>>,
+
+    // Visible chunks with translucent quads, farthest first
+    translucent_order : Vec<Vector3<i32>>,
 }
 
 impl ChunkRenderer {
-    pub fn new(texture_atlas: Atlas<BlockState>) -> Self {
-        return Self {
+    // Limits the amount of buffers created per frame, so streaming in lots of chunks doesn't stall rendering
+    const MAX_UPLOADS_PER_FRAME: usize = 4;
+
+    /// The camera is bound to group 1 by whoever draws the renderer.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, config: &wgpu::SurfaceConfiguration, camera_layout: &wgpu::BindGroupLayout,
+               resources: &ResourceManager, registry: Arc<BlockRegistry>, texture_atlas: Atlas<TextureId>) -> Result<Self> {
+        let textures = registry.textures().len();
+        if textures > TileTable::MAX_TILES {
+            bail!("Too many block textures: {}, at most {} are supported", textures, TileTable::MAX_TILES);
+        }
+
+        let mut tiles = TileTable::default();
+        for id in 0 .. textures {
+            let uv = texture_atlas.uv(&(id as TextureId));
+            tiles.tiles[id] = Tile {
+                rect : [uv.min.x, uv.min.y, uv.max.x, uv.max.y],
+                page : texture_atlas.page(&(id as TextureId)),
+                .. Default::default()
+            };
+        }
+
+        let tile_table = Uniform::new(device);
+        tile_table.update(queue, &tiles);
+
+        let groups = [texture_atlas.layout(), camera_layout, tile_table.layout()];
+        let shader = resources.shader(device, "chunk.wgsl")?;
+        let pipeline = utils::chunk_pipeline(device, &shader, config, &groups, false);
+        let translucent = utils::chunk_pipeline(device, &shader, config, &groups, true);
+
+        return Ok(Self {
             texture_atlas,
-            chunk_meshes: vec![],
+            chunk_meshes: HashMap::new(),
+            mesher: ChunkMesher::new(registry),
+            stats: ChunkRenderStats::default(),
+
+            pipeline,
+            translucent,
+            tile_table,
+            indices: QuadIndices::new(device, 1024),
+
+            pending: HashMap::new(),
+            next_version: 0,
+
+            visible: vec![],
+            translucent_order: vec![],
+        });
+    }
+
+    /// Queues every chunk the world marked as dirty for meshing, uploads finished meshes
+    /// and drops meshes of unloaded chunks.
+    pub fn update(&mut self, device: &wgpu::Device, world: &mut World) {
+        world.update_light();
+        for position in world.take_dirty() {
+            if let Some(blocks) = world.padded(position) {
+                self.next_version += 1;
+                self.pending.insert(position, self.next_version);
+                self.mesher.submit(MeshJob {
+                    position,
+                    version: self.next_version,
+                    blocks,
+                });
+            }
+        }
+
+        let mut uploads = 0;
+        while uploads < Self::MAX_UPLOADS_PER_FRAME {
+            let result = match self.mesher.poll() {
+                Some(result) => result,
+                None => break,
+            };
+
+            // Skip meshes that were superseded by a newer job or whose chunk is gone
+            if self.pending.get(&result.position) != Some(&result.version) { continue; }
+            self.pending.remove(&result.position);
+            if !world.chunks.contains_key(&result.position) { continue; }
+
+            let quads = result.vertices.opaque.len().max(result.vertices.translucent.len()) / 4;
+            self.indices.reserve(device, quads as u32);
+            if let Some(mesh) = self.chunk_meshes.get_mut(&result.position) {
+                mesh.upload(device, result.vertices);
+            } else {
+                self.chunk_meshes.insert(result.position, ChunkMesh::new(device, result.position, result.vertices));
+            }
+
+            uploads += 1;
+        }
+
+        self.chunk_meshes.retain(|position, _| world.chunks.contains_key(position));
+    }
+
+    /// Picks the chunks inside of the camera's frustum for drawing.
+    pub fn cull(&mut self, frustum: &Frustum) {
+        let size = CHUNK_SIZE as f32;
+        self.visible.clear();
+        for position in self.chunk_meshes.keys() {
+            let min = World::origin(*position).cast::<f32>().unwrap();
+            if frustum.intersects_aabb(min, min + vec3(size, size, size)) {
+                self.visible.push(*position);
+            }
+        }
+
+        self.stats = ChunkRenderStats {
+            drawn  : self.visible.len(),
+            culled : self.chunk_meshes.len() - self.visible.len(),
         };
     }
 
-    // Might need to pass chunks instead of blocks later
-    pub fn add(&mut self, device: &wgpu::Device, chunk: &Chunk) {
-        self.chunk_meshes.push(ChunkMesh::new(device, &chunk.blocks, &self.texture_atlas))
+    /// Orders visible translucent geometry back to front for `draw_translucent`, call after `cull`.
+    pub fn sort_translucent(&mut self, queue: &wgpu::Queue, eye: Vector3<f32>) {
+        let half = CHUNK_SIZE as f32 / 2.0;
+        let distance = |mesh: &ChunkMesh| (mesh.origin + vec3(half, half, half) - eye).magnitude2();
+
+        let mut order: Vec<_> = self.visible.iter()
+            .filter_map(|position| self.chunk_meshes.get(position).filter(|mesh| mesh.has_translucent()).map(|mesh| (distance(mesh), *position)))
+            .collect();
+
+        order.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        self.translucent_order = order.into_iter().map(|(_, position)| position).collect();
+
+        for position in &self.translucent_order {
+            if let Some(mesh) = self.chunk_meshes.get_mut(position) {
+                mesh.sort_translucent(queue, eye);
+            }
+        }
+    }
+
+    /// Blends translucent quads over everything drawn so far, so it has to come after all opaque geometry.
+    pub fn draw_translucent<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.translucent_order.is_empty() {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.translucent);
+        self.texture_atlas.bind(render_pass, 0);
+        self.tile_table.bind(render_pass, 2);
+        self.indices.bind(render_pass);
+        for position in &self.translucent_order {
+            if let Some(mesh) = self.chunk_meshes.get(position) {
+                mesh.draw_translucent(render_pass);
+            }
+        }
     }
 }
 
 impl Drawable for ChunkRenderer {
     fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
         self.texture_atlas.bind(render_pass, 0);
-        for mesh in &self.chunk_meshes {
-            mesh.draw(render_pass);
+        self.tile_table.bind(render_pass, 2);
+        self.indices.bind(render_pass);
+        for position in &self.visible {
+            if let Some(mesh) = self.chunk_meshes.get(position) {
+                mesh.draw(render_pass);
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,6 @@
 pub mod chunk;
 pub mod chunk_mesh;
-pub mod chunk_renderer;
\ No newline at end of file
+pub mod chunk_renderer;
+pub mod chunk_mesher;
+pub mod block_storage;
+pub mod chunk_loader;
\ No newline at end of file
@@ -0,0 +1,620 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::Arc};
+
+use cgmath::{Vector3, vec3};
+
+use super::{block_registry::BlockRegistry, chunk::chunk::{Chunk, CHUNK_SIZE, CHUNK_VOLUME}, world::World};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// Light levels of a block, each from 0 to `MAX_LIGHT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Light {
+    pub sky   : u8,
+    pub block : u8,
+}
+
+impl Light {
+    /// Open sky, how everything is lit when there's no lighting.
+    pub const FULL: Light = Light { sky: MAX_LIGHT, block: 0 };
+}
+
+/// Light of every block in a chunk, both levels packed into a byte.
+#[derive(Clone)]
+pub struct LightMap {
+    data: Vec<u8>,
+}
+
+impl LightMap {
+    pub fn new() -> Self {
+        return Self {
+            data: vec![0; CHUNK_VOLUME],
+        };
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Light {
+        let packed = self.data[Chunk::index_unchecked(x, y, z)];
+        return Light { sky: packed >> 4, block: packed & 0xF };
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, light: Light) {
+        self.data[Chunk::index_unchecked(x, y, z)] = (light.sky << 4) | light.block;
+    }
+
+    fn level(&self, channel: Channel, index: usize) -> u8 {
+        return match channel {
+            Channel::Sky   => self.data[index] >> 4,
+            Channel::Block => self.data[index] & 0xF,
+        };
+    }
+
+    fn set_level(&mut self, channel: Channel, index: usize, level: u8) {
+        self.data[index] = match channel {
+            Channel::Sky   => (self.data[index] & 0xF) | (level << 4),
+            Channel::Block => (self.data[index] & 0xF0) | level,
+        };
+    }
+
+    // Inverse of `Chunk::index_unchecked`
+    const fn coordinates(index: usize) -> (i32, i32, i32) {
+        let size = CHUNK_SIZE;
+        return ((index % size) as i32, (index / size % size) as i32, (index / (size * size)) as i32);
+    }
+}
+
+impl Default for LightMap {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+const DOWN: Vector3<i32> = vec3(0, -1, 0);
+const DIRECTIONS: [Vector3<i32>; 6] = [vec3(0, 1, 0), DOWN, vec3(0, 0, 1), vec3(0, 0, -1), vec3(1, 0, 0), vec3(-1, 0, 0)];
+
+/// Flood-fill light engine.
+/// Block light spreads from emitting blocks, losing a level per block. Sky light enters through the top
+/// of chunks without a loaded chunk above, goes straight down without losing anything and spreads sideways like block light.
+/// Opaque blocks and unloaded chunks stop light.
+pub struct Lighting {
+    registry : Arc<BlockRegistry>,
+    maps     : HashMap<Vector3<i32>, LightMap>,
+
+    // Chunks whose padded view saw light changes since the last `take_touched`
+    touched  : HashSet<Vector3<i32>>,
+}
+
+impl Lighting {
+    pub fn new(registry: Arc<BlockRegistry>) -> Self {
+        return Self {
+            registry,
+            maps    : HashMap::new(),
+            touched : HashSet::new(),
+        };
+    }
+
+    /// Returns `None` if the block's chunk isn't lit.
+    pub fn get(&self, position: Vector3<i32>) -> Option<Light> {
+        let (chunk_position, (x, y, z)) = World::split(position);
+        return self.maps.get(&chunk_position).map(|map| map.get(x, y, z));
+    }
+
+    /// Lights a freshly loaded chunk, spreading light into and out of it.
+    pub fn chunk_loaded(&mut self, chunks: &HashMap<Vector3<i32>, Chunk>, position: Vector3<i32>) {
+        let chunk = match chunks.get(&position) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+
+        let origin = World::origin(position);
+        let size = CHUNK_SIZE as i32;
+        let open = !chunks.contains_key(&(position - DOWN));
+        let opaque: Vec<bool> = (0 .. CHUNK_VOLUME).map(|i| self.registry.is_opaque(chunk.blocks.get(i))).collect();
+
+        // Fill the chunk on its own first, going through the map directly is a lot faster than `propagate`
+        let mut map = LightMap::new();
+        for channel in [Channel::Sky, Channel::Block] {
+            let mut queue = VecDeque::new();
+            for (i, opaque) in opaque.iter().enumerate() {
+                let (x, y, z) = LightMap::coordinates(i);
+                let source = match channel {
+                    Channel::Block => self.registry.light(chunk.blocks.get(i)),
+                    Channel::Sky   => if open && y == size - 1 && !opaque { MAX_LIGHT } else { 0 },
+                };
+
+                // Light of the neighbours flows in over the border
+                let mut level = source;
+                if !opaque {
+                    for direction in DIRECTIONS {
+                        let (nx, ny, nz) = (x + direction.x, y + direction.y, z + direction.z);
+                        let outside = !(0 .. size).contains(&nx) || !(0 .. size).contains(&ny) || !(0 .. size).contains(&nz);
+                        if outside {
+                            if let Some(neighbour) = self.level(channel, origin + vec3(nx, ny, nz)) {
+                                level = level.max(Self::spread(channel, -direction, neighbour));
+                            }
+                        }
+                    }
+                }
+
+                if level > 0 {
+                    map.set_level(channel, i, level);
+                    queue.push_back(i);
+                }
+            }
+
+            while let Some(i) = queue.pop_front() {
+                let (x, y, z) = LightMap::coordinates(i);
+                let level = map.level(channel, i);
+                for direction in DIRECTIONS {
+                    let (nx, ny, nz) = (x + direction.x, y + direction.y, z + direction.z);
+                    if !(0 .. size).contains(&nx) || !(0 .. size).contains(&ny) || !(0 .. size).contains(&nz) {
+                        continue;
+                    }
+
+                    let neighbour = Chunk::index_unchecked(nx as usize, ny as usize, nz as usize);
+                    let spread = Self::spread(channel, direction, level);
+                    if !opaque[neighbour] && map.level(channel, neighbour) < spread {
+                        map.set_level(channel, neighbour, spread);
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+
+        self.maps.insert(position, map);
+        for dx in -1 ..= 1 {
+            for dy in -1 ..= 1 {
+                for dz in -1 ..= 1 {
+                    self.touched.insert(position + vec3(dx, dy, dz));
+                }
+            }
+        }
+
+        // Then let the light of the border blocks out into the neighbours
+        for channel in [Channel::Sky, Channel::Block] {
+            let mut queue = VecDeque::new();
+            for z in 0 .. size {
+                for y in 0 .. size {
+                    for x in 0 .. size {
+                        let border = x == 0 || y == 0 || z == 0 || x == size - 1 || y == size - 1 || z == size - 1;
+                        if border {
+                            queue.push_back(origin + vec3(x, y, z));
+                        }
+                    }
+                }
+            }
+
+            self.propagate(chunks, channel, queue);
+        }
+
+        // The chunk below used to get sky light straight from above, now it comes through this one
+        let below = position + DOWN;
+        if self.maps.contains_key(&below) {
+            let top = World::origin(below).y + size - 1;
+            let mut exposed = vec![];
+            for z in 0 .. size {
+                for x in 0 .. size {
+                    let block = vec3(origin.x + x, top, origin.z + z);
+                    if self.level(Channel::Sky, block) == Some(MAX_LIGHT) {
+                        exposed.push(block);
+                    }
+                }
+            }
+
+            self.relight(chunks, &exposed);
+        }
+    }
+
+    /// Takes the light that came from an unloaded chunk away from its neighbours, `chunks` must not contain it anymore.
+    pub fn chunk_removed(&mut self, chunks: &HashMap<Vector3<i32>, Chunk>, position: Vector3<i32>) {
+        if self.maps.remove(&position).is_none() {
+            return;
+        }
+
+        // Light only moves between faces, so whatever came out of the chunk went through the blocks right next to it.
+        // The chunk below gets open sky through its top layer as well.
+        let origin = World::origin(position);
+        let size = CHUNK_SIZE as i32;
+        let mut border = vec![];
+        for a in 0 .. size {
+            for b in 0 .. size {
+                border.extend([vec3(-1, a, b), vec3(size, a, b), vec3(a, -1, b), vec3(a, size, b), vec3(a, b, -1), vec3(a, b, size)].map(|offset| origin + offset));
+            }
+        }
+
+        border.retain(|block| self.maps.contains_key(&World::split(*block).0));
+        self.relight(chunks, &border);
+    }
+
+    /// Light of a whole chunk, `None` if it isn't lit.
+    pub fn map(&self, position: Vector3<i32>) -> Option<&LightMap> {
+        return self.maps.get(&position);
+    }
+
+    /// Recomputes the light around blocks after they changed.
+    pub fn relight(&mut self, chunks: &HashMap<Vector3<i32>, Chunk>, positions: &[Vector3<i32>]) {
+        for channel in [Channel::Sky, Channel::Block] {
+            // Take away the light that went through the blocks, then fill the holes from what's left around them
+            let mut darkened = VecDeque::new();
+            for position in positions {
+                if let Some(level) = self.level(channel, *position) {
+                    self.set_level(channel, *position, 0);
+                    darkened.push_back((*position, level));
+                }
+            }
+
+            let mut queue = self.unpropagate(chunks, channel, darkened);
+            for position in positions {
+                let source = self.source(chunks, channel, *position);
+                if source > 0 {
+                    self.set_level(channel, *position, source);
+                    queue.push_back(*position);
+                }
+
+                if !self.is_opaque(chunks, *position) {
+                    queue.extend(DIRECTIONS.iter().map(|direction| position + direction));
+                }
+            }
+
+            self.propagate(chunks, channel, queue);
+        }
+    }
+
+    /// Drains the set of chunks whose meshes are out of date because of light changes, loaded or not.
+    pub fn take_touched(&mut self) -> Vec<Vector3<i32>> {
+        return self.touched.drain().collect();
+    }
+
+    // Spreads light outwards from the queued blocks
+    fn propagate(&mut self, chunks: &HashMap<Vector3<i32>, Chunk>, channel: Channel, mut queue: VecDeque<Vector3<i32>>) {
+        while let Some(position) = queue.pop_front() {
+            let level = match self.level(channel, position) {
+                Some(level) if level > 1 => level,
+                _ => continue,
+            };
+
+            for direction in DIRECTIONS {
+                let neighbour = position + direction;
+                let spread = Self::spread(channel, direction, level);
+                if self.level(channel, neighbour).is_some_and(|current| current < spread) && !self.is_opaque(chunks, neighbour) {
+                    self.set_level(channel, neighbour, spread);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+
+    // Level light has after moving a block in `direction`
+    fn spread(channel: Channel, direction: Vector3<i32>, level: u8) -> u8 {
+        // Sunlight goes down without getting any weaker
+        if channel == Channel::Sky && direction == DOWN && level == MAX_LIGHT {
+            return MAX_LIGHT;
+        }
+
+        return level.saturating_sub(1);
+    }
+
+    // Darkens every block that got its light through the queued `(block, previous level)` pairs.
+    // Returns the brighter blocks at the edge of the darkened area, those have to spread their light back in.
+    fn unpropagate(&mut self, chunks: &HashMap<Vector3<i32>, Chunk>, channel: Channel, mut queue: VecDeque<(Vector3<i32>, u8)>) -> VecDeque<Vector3<i32>> {
+        let mut relight = VecDeque::new();
+        while let Some((position, level)) = queue.pop_front() {
+            for direction in DIRECTIONS {
+                let neighbour = position + direction;
+                let current = match self.level(channel, neighbour) {
+                    Some(current) if current > 0 => current,
+                    _ => continue,
+                };
+
+                let dependent = current < level || (channel == Channel::Sky && direction == DOWN && level == MAX_LIGHT && current == MAX_LIGHT);
+                if dependent {
+                    self.set_level(channel, neighbour, 0);
+                    queue.push_back((neighbour, current));
+
+                    // Light sources keep shining on their own
+                    let source = self.source(chunks, channel, neighbour);
+                    if source > 0 {
+                        self.set_level(channel, neighbour, source);
+                        relight.push_back(neighbour);
+                    }
+                } else {
+                    relight.push_back(neighbour);
+                }
+            }
+        }
+
+        return relight;
+    }
+
+    // Light a block has by itself: emitted block light, or sky light at the top of the loaded world
+    fn source(&self, chunks: &HashMap<Vector3<i32>, Chunk>, channel: Channel, position: Vector3<i32>) -> u8 {
+        let (chunk_position, (x, y, z)) = World::split(position);
+        let chunk = match chunks.get(&chunk_position) {
+            Some(chunk) => chunk,
+            None => return 0,
+        };
+
+        let block_state = chunk.get(x, y, z);
+        return match channel {
+            Channel::Block => self.registry.light(block_state),
+            Channel::Sky => {
+                let open = y == CHUNK_SIZE - 1 && !chunks.contains_key(&(chunk_position - DOWN));
+                if open && !self.registry.is_opaque(block_state) { MAX_LIGHT } else { 0 }
+            }
+        };
+    }
+
+    // Blocks of unloaded chunks count as opaque, so light doesn't leak into them
+    fn is_opaque(&self, chunks: &HashMap<Vector3<i32>, Chunk>, position: Vector3<i32>) -> bool {
+        let (chunk_position, (x, y, z)) = World::split(position);
+        return match chunks.get(&chunk_position) {
+            Some(chunk) => self.registry.is_opaque(chunk.get(x, y, z)),
+            None => true,
+        };
+    }
+
+    fn level(&self, channel: Channel, position: Vector3<i32>) -> Option<u8> {
+        return self.get(position).map(|light| match channel {
+            Channel::Sky   => light.sky,
+            Channel::Block => light.block,
+        });
+    }
+
+    fn set_level(&mut self, channel: Channel, position: Vector3<i32>, level: u8) {
+        let (chunk_position, (x, y, z)) = World::split(position);
+        if let Some(map) = self.maps.get_mut(&chunk_position) {
+            let mut light = map.get(x, y, z);
+            match channel {
+                Channel::Sky   => light.sky = level,
+                Channel::Block => light.block = level,
+            }
+
+            map.set(x, y, z, light);
+
+            // Blocks on the border are part of the neighbours' padded views as well
+            let border = |local: usize| local == 0 || local == CHUNK_SIZE - 1;
+            if border(x) || border(y) || border(z) {
+                self.touched.extend(World::sharing(position));
+            } else {
+                self.touched.insert(chunk_position);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::chunk::chunk::BlockState;
+
+    const STONE: BlockState = BlockState(1);
+    const LAMP: BlockState = BlockState(2);
+    const GLASS: BlockState = BlockState(3);
+
+    fn registry() -> Arc<BlockRegistry> {
+        return Arc::new(BlockRegistry::from_ron(r#"#![enable(implicit_some)] [
+            (id: 1, name: "stone", textures: (all: "test")),
+            (id: 2, name: "lamp",  textures: (all: "test"), light: 14),
+            (id: 3, name: "glass", textures: (all: "test"), transparency: Translucent),
+        ]"#).unwrap());
+    }
+
+    // Lights chunks in the given order
+    fn lit(chunks: &HashMap<Vector3<i32>, Chunk>, order: &[Vector3<i32>]) -> Lighting {
+        let mut lighting = Lighting::new(registry());
+        let mut loaded = HashMap::new();
+        for position in order {
+            loaded.insert(*position, chunks[position].clone());
+            lighting.chunk_loaded(&loaded, *position);
+        }
+
+        return lighting;
+    }
+
+    fn set(chunks: &mut HashMap<Vector3<i32>, Chunk>, position: Vector3<i32>, block_state: BlockState) {
+        let (chunk_position, (x, y, z)) = World::split(position);
+        chunks.get_mut(&chunk_position).unwrap().set(x, y, z, block_state);
+    }
+
+    fn assert_same(a: &Lighting, b: &Lighting) {
+        assert_eq!(a.maps.len(), b.maps.len());
+        for (position, map) in &a.maps {
+            if map.data != b.maps[position].data {
+                let index = map.data.iter().zip(&b.maps[position].data).position(|(a, b)| a != b).unwrap();
+                panic!("Light of chunk {:?} differs at {:?}", position, LightMap::coordinates(index));
+            }
+        }
+    }
+
+    // Chunk with a stone roof, dark inside unless something lights it
+    fn covered() -> HashMap<Vector3<i32>, Chunk> {
+        let mut chunks = HashMap::new();
+        chunks.insert(vec3(0, 1, 0), Chunk::filled(STONE));
+        chunks.insert(vec3(0, 0, 0), Chunk::new());
+        return chunks;
+    }
+
+    #[test]
+    fn open_sky_lights_everything() {
+        let mut chunks = HashMap::new();
+        chunks.insert(vec3(0, 0, 0), Chunk::new());
+        let lighting = lit(&chunks, &[vec3(0, 0, 0)]);
+        for y in 0 .. CHUNK_SIZE as i32 {
+            assert_eq!(lighting.get(vec3(3, y, 7)), Some(Light { sky: MAX_LIGHT, block: 0 }));
+        }
+
+        assert_eq!(lighting.get(vec3(0, 32, 0)), None);
+    }
+
+    #[test]
+    fn block_light_fades_with_distance() {
+        let mut chunks = covered();
+        set(&mut chunks, vec3(16, 16, 16), LAMP);
+        let lighting = lit(&chunks, &[vec3(0, 1, 0), vec3(0, 0, 0)]);
+
+        for distance in 0 .. 16 {
+            let expected = 14u8.saturating_sub(distance);
+            assert_eq!(lighting.get(vec3(16 + distance as i32, 16, 16)).unwrap().block, expected);
+            assert_eq!(lighting.get(vec3(16, 16 - distance as i32, 16)).unwrap().block, expected);
+        }
+
+        assert_eq!(lighting.get(vec3(18, 13, 15)).unwrap().block, 14 - 6);
+        assert_eq!(lighting.get(vec3(5, 5, 5)).unwrap().sky, 0);
+    }
+
+    #[test]
+    fn removing_a_light_source_darkens_its_surroundings() {
+        let mut chunks = covered();
+        set(&mut chunks, vec3(16, 16, 16), LAMP);
+        set(&mut chunks, vec3(20, 16, 16), LAMP);
+        let mut lighting = lit(&chunks, &[vec3(0, 1, 0), vec3(0, 0, 0)]);
+
+        set(&mut chunks, vec3(16, 16, 16), BlockState::AIR);
+        lighting.relight(&chunks, &[vec3(16, 16, 16)]);
+        assert_eq!(lighting.get(vec3(16, 16, 16)).unwrap().block, 10);
+        assert_eq!(lighting.get(vec3(12, 16, 16)).unwrap().block, 6);
+        assert_same(&lighting, &lit(&chunks, &[vec3(0, 1, 0), vec3(0, 0, 0)]));
+
+        set(&mut chunks, vec3(20, 16, 16), BlockState::AIR);
+        lighting.relight(&chunks, &[vec3(20, 16, 16)]);
+        assert!(lighting.maps[&vec3(0, 0, 0)].data.iter().all(|light| *light == 0));
+    }
+
+    #[test]
+    fn walls_block_light() {
+        let mut chunks = covered();
+        set(&mut chunks, vec3(16, 16, 16), LAMP);
+        let mut lighting = lit(&chunks, &[vec3(0, 1, 0), vec3(0, 0, 0)]);
+        assert_eq!(lighting.get(vec3(18, 16, 16)).unwrap().block, 12);
+
+        // A wall right next to the lamp, light has to go around it
+        for y in 0 .. CHUNK_SIZE as i32 {
+            for z in 0 .. CHUNK_SIZE as i32 {
+                if (y, z) != (0, 0) {
+                    set(&mut chunks, vec3(17, y, z), STONE);
+                    lighting.relight(&chunks, &[vec3(17, y, z)]);
+                }
+            }
+        }
+
+        assert_eq!(lighting.get(vec3(17, 16, 16)).unwrap().block, 0);
+        assert_eq!(lighting.get(vec3(18, 16, 16)).unwrap().block, 0);
+        assert_eq!(lighting.get(vec3(15, 16, 16)).unwrap().block, 13);
+        assert_same(&lighting, &lit(&chunks, &[vec3(0, 0, 0), vec3(0, 1, 0)]));
+    }
+
+    #[test]
+    fn sky_light_goes_straight_down_through_holes() {
+        let mut chunks = HashMap::new();
+        chunks.insert(vec3(0, 0, 0), Chunk::new());
+        for x in 0 .. CHUNK_SIZE {
+            for z in 0 .. CHUNK_SIZE {
+                chunks.get_mut(&vec3(0, 0, 0)).unwrap().set(x, 31, z, STONE);
+            }
+        }
+
+        let mut lighting = lit(&chunks, &[vec3(0, 0, 0)]);
+        assert_eq!(lighting.get(vec3(10, 0, 10)).unwrap().sky, 0);
+
+        // Glass lets sky light through as well
+        set(&mut chunks, vec3(10, 31, 10), GLASS);
+        lighting.relight(&chunks, &[vec3(10, 31, 10)]);
+        assert_eq!(lighting.get(vec3(10, 0, 10)).unwrap().sky, MAX_LIGHT);
+        assert_eq!(lighting.get(vec3(11, 0, 10)).unwrap().sky, MAX_LIGHT - 1);
+        assert_eq!(lighting.get(vec3(13, 5, 12)).unwrap().sky, MAX_LIGHT - 5);
+        assert_same(&lighting, &lit(&chunks, &[vec3(0, 0, 0)]));
+
+        set(&mut chunks, vec3(10, 31, 10), STONE);
+        lighting.relight(&chunks, &[vec3(10, 31, 10)]);
+        assert!(lighting.maps[&vec3(0, 0, 0)].data.iter().all(|light| *light == 0));
+    }
+
+    #[test]
+    fn light_crosses_chunk_borders_in_any_load_order() {
+        let mut chunks = HashMap::new();
+        for x in -1 ..= 0 {
+            for z in -1 ..= 0 {
+                chunks.insert(vec3(x, 1, z), Chunk::filled(STONE));
+                chunks.insert(vec3(x, 0, z), Chunk::new());
+            }
+        }
+
+        set(&mut chunks, vec3(1, 5, 1), LAMP);
+        set(&mut chunks, vec3(-3, 30, -20), LAMP);
+        set(&mut chunks, vec3(-1, 31, 0), GLASS);
+
+        let mut order: Vec<_> = chunks.keys().copied().collect();
+        order.sort_by_key(|position| (position.x, position.y, position.z));
+        let first = lit(&chunks, &order);
+        assert_eq!(first.get(vec3(-2, 5, 1)).unwrap().block, 11);
+        assert_eq!(first.get(vec3(1, 5, -3)).unwrap().block, 10);
+
+        order.reverse();
+        assert_same(&first, &lit(&chunks, &order));
+
+        order.rotate_left(3);
+        assert_same(&first, &lit(&chunks, &order));
+    }
+
+    #[test]
+    fn unloading_takes_light_away_from_neighbours() {
+        let mut chunks = HashMap::new();
+        chunks.insert(vec3(0, 1, 0), Chunk::filled(STONE));
+        chunks.insert(vec3(1, 1, 0), Chunk::filled(STONE));
+        chunks.insert(vec3(0, 0, 0), Chunk::new());
+        chunks.insert(vec3(1, 0, 0), Chunk::new());
+        set(&mut chunks, vec3(30, 10, 10), LAMP);
+
+        let order = [vec3(0, 1, 0), vec3(1, 1, 0), vec3(0, 0, 0), vec3(1, 0, 0)];
+        let mut lighting = lit(&chunks, &order);
+        assert_eq!(lighting.get(vec3(33, 10, 10)).unwrap().block, 11);
+
+        chunks.remove(&vec3(0, 0, 0));
+        lighting.chunk_removed(&chunks, vec3(0, 0, 0));
+        assert!(lighting.get(vec3(30, 10, 10)).is_none());
+        assert!(lighting.maps[&vec3(1, 0, 0)].data.iter().all(|light| *light == 0));
+
+        // The chunk below a removed one is open to the sky again
+        chunks.remove(&vec3(1, 1, 0));
+        lighting.chunk_removed(&chunks, vec3(1, 1, 0));
+        assert_eq!(lighting.get(vec3(40, 0, 3)).unwrap().sky, MAX_LIGHT);
+        assert_same(&lighting, &lit(&chunks, &[vec3(0, 1, 0), vec3(1, 0, 0)]));
+    }
+
+    #[test]
+    fn random_edits_match_lighting_from_scratch() {
+        let mut chunks = HashMap::new();
+        let mut order = vec![];
+        for x in -1 ..= 0 {
+            for y in -1 ..= 0 {
+                chunks.insert(vec3(x, y, 0), Chunk::new());
+                order.push(vec3(x, y, 0));
+            }
+        }
+
+        let mut lighting = lit(&chunks, &order);
+
+        // Small linear congruential generator, the sequence has to be the same on every run
+        let mut state = 987654321u64;
+        let mut next = |range: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            return (state >> 33) % range;
+        };
+
+        for step in 0 .. 300 {
+            // Edits cluster near the chunk borders, where most of the trouble is
+            let position = vec3(next(12) as i32 - 6, next(64) as i32 - 32, next(32) as i32);
+            let block_state = [BlockState::AIR, STONE, STONE, LAMP, GLASS][next(5) as usize];
+            set(&mut chunks, position, block_state);
+            lighting.relight(&chunks, &[position]);
+
+            if step % 50 == 49 {
+                assert_same(&lighting, &lit(&chunks, &order));
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::HashMap, sync::{Arc, mpsc::{self, Sender, Receiver}}};
+
+use cgmath::Vector3;
+
+use super::{block_registry::BlockRegistry, chunk::chunk::{Chunk, BlockState}, light::{Lighting, LightMap}, world::World};
+
+pub enum LightJob {
+    Load(Vector3<i32>, Chunk),
+    Remove(Vector3<i32>),
+    SetBlock(Vector3<i32>, BlockState),
+}
+
+/// Light of every chunk whose light changed since the last update.
+pub struct LightUpdate {
+    pub maps: Vec<(Vector3<i32>, LightMap)>,
+}
+
+// Copy of the world's chunks, light is computed from these so the world itself never has to be shared
+struct LightState {
+    lighting : Lighting,
+    chunks   : HashMap<Vector3<i32>, Chunk>,
+}
+
+impl LightState {
+    fn process(&mut self, jobs: impl IntoIterator<Item = LightJob>) -> LightUpdate {
+        for job in jobs {
+            match job {
+                LightJob::Load(position, chunk) => {
+                    self.chunks.insert(position, chunk);
+                    self.lighting.chunk_loaded(&self.chunks, position);
+                }
+
+                LightJob::Remove(position) => {
+                    if self.chunks.remove(&position).is_some() {
+                        self.lighting.chunk_removed(&self.chunks, position);
+                    }
+                }
+
+                LightJob::SetBlock(position, block_state) => {
+                    let (chunk_position, (x, y, z)) = World::split(position);
+                    if let Some(chunk) = self.chunks.get_mut(&chunk_position) {
+                        chunk.set(x, y, z, block_state);
+                        self.lighting.relight(&self.chunks, &[position]);
+                    }
+                }
+            }
+        }
+
+        let maps = self.lighting.take_touched().into_iter()
+            .filter_map(|position| self.lighting.map(position).map(|map| (position, map.clone())))
+            .collect();
+
+        return LightUpdate { maps };
+    }
+}
+
+/// Keeps the light of a world up to date on a worker thread, so flood fills never hold up a frame.
+/// Jobs are handled in the order they're submitted, updates come back in the same order.
+/// There are no threads on wasm, so jobs are handled right away when submitted.
+pub struct LightWorker {
+    updates : Receiver<LightUpdate>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    jobs    : Option<Sender<LightJob>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    worker  : Option<std::thread::JoinHandle<()>>,
+
+    #[cfg(target_arch = "wasm32")]
+    sender  : Sender<LightUpdate>,
+    #[cfg(target_arch = "wasm32")]
+    state   : LightState,
+}
+
+impl LightWorker {
+    pub fn new(registry: Arc<BlockRegistry>) -> Self {
+        let (sender, updates) = mpsc::channel::<LightUpdate>();
+        let state = LightState {
+            lighting : Lighting::new(registry),
+            chunks   : HashMap::new(),
+        };
+
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                return Self {
+                    updates,
+                    sender,
+                    state,
+                };
+            } else {
+                let (jobs, job_receiver) = mpsc::channel::<LightJob>();
+                let worker = std::thread::Builder::new().name("lighting".into()).spawn(move || {
+                    let mut state = state;
+
+                    // Everything that queued up is handled together, chunks tend to arrive in bursts
+                    while let Ok(job) = job_receiver.recv() {
+                        let update = state.process(std::iter::once(job).chain(job_receiver.try_iter()));
+                        if !update.maps.is_empty() && sender.send(update).is_err() {
+                            break;
+                        }
+                    }
+                }).unwrap();
+
+                return Self {
+                    updates,
+                    jobs   : Some(jobs),
+                    worker : Some(worker),
+                };
+            }
+        }
+    }
+
+    pub fn submit(&mut self, job: LightJob) {
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                self.sender.send(self.state.process([job])).unwrap();
+            } else {
+                if let Some(jobs) = &self.jobs {
+                    jobs.send(job).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Returns the next finished update if there is one, never blocks.
+    pub fn poll(&mut self) -> Option<LightUpdate> {
+        return self.updates.try_recv().ok();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for LightWorker {
+    fn drop(&mut self) {
+        // Closing the job channel stops the worker
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            worker.join().unwrap();
+        }
+    }
+}
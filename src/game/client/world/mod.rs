@@ -1,3 +1,10 @@
 pub mod player_camera;
 pub mod player;
-pub mod chunk;
\ No newline at end of file
+pub mod chunk;
+pub mod world;
+pub mod block_registry;
+pub mod noise;
+pub mod terrain_generator;
+pub mod raycast;
+pub mod light;
+pub mod light_worker;
\ No newline at end of file
@@ -0,0 +1,126 @@
+/// Seeded gradient noise. Only uses integer hashing and basic float arithmetic,
+/// so the same seed and coordinates always give bit-identical results.
+#[derive(Debug, Clone, Copy)]
+pub struct Noise {
+    seed: u64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        return Self { seed };
+    }
+
+    /// Roughly in `[-1, 1]`, with features about 1 unit apart.
+    pub fn sample2(&self, x: f64, z: f64) -> f64 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let (ix, iz) = (x0 as i64, z0 as i64);
+
+        let g00 = self.gradient2(ix,     iz,     fx,       fz      );
+        let g10 = self.gradient2(ix + 1, iz,     fx - 1.0, fz      );
+        let g01 = self.gradient2(ix,     iz + 1, fx,       fz - 1.0);
+        let g11 = self.gradient2(ix + 1, iz + 1, fx - 1.0, fz - 1.0);
+
+        let (u, v) = (fade(fx), fade(fz));
+        return lerp(lerp(g00, g10, u), lerp(g01, g11, u), v);
+    }
+
+    /// Roughly in `[-1, 1]`, with features about 1 unit apart.
+    pub fn sample3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (ix, iy, iz) = (x0 as i64, y0 as i64, z0 as i64);
+
+        let mut corners = [0.0; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let (dx, dy, dz) = ((i & 1) as i64, ((i >> 1) & 1) as i64, ((i >> 2) & 1) as i64);
+            *corner = self.gradient3(ix + dx, iy + dy, iz + dz, fx - dx as f64, fy - dy as f64, fz - dz as f64);
+        }
+
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        let x00 = lerp(corners[0], corners[1], u);
+        let x10 = lerp(corners[2], corners[3], u);
+        let x01 = lerp(corners[4], corners[5], u);
+        let x11 = lerp(corners[6], corners[7], u);
+        return lerp(lerp(x00, x10, v), lerp(x01, x11, v), w);
+    }
+
+    /// Fractal sum of `octaves` layers, each with double the frequency and half the amplitude of the previous one.
+    pub fn fbm2(&self, x: f64, z: f64, octaves: u32) -> f64 {
+        let (mut sum, mut amplitude, mut frequency, mut total) = (0.0, 1.0, 1.0, 0.0);
+        for octave in 0 .. octaves {
+            let layer = Noise::new(self.seed.wrapping_add(octave as u64));
+            sum += layer.sample2(x * frequency, z * frequency) * amplitude;
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        return sum / total;
+    }
+
+    pub fn fbm3(&self, x: f64, y: f64, z: f64, octaves: u32) -> f64 {
+        let (mut sum, mut amplitude, mut frequency, mut total) = (0.0, 1.0, 1.0, 0.0);
+        for octave in 0 .. octaves {
+            let layer = Noise::new(self.seed.wrapping_add(octave as u64));
+            sum += layer.sample3(x * frequency, y * frequency, z * frequency) * amplitude;
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+
+        return sum / total;
+    }
+
+    fn gradient2(&self, x: i64, z: i64, dx: f64, dz: f64) -> f64 {
+        // 8 directions: axes and diagonals
+        return match hash(self.seed, x, 0, z) & 7 {
+            0 =>  dx + dz,
+            1 =>  dx - dz,
+            2 => -dx + dz,
+            3 => -dx - dz,
+            4 =>  dx,
+            5 => -dx,
+            6 =>  dz,
+            _ => -dz,
+        };
+    }
+
+    fn gradient3(&self, x: i64, y: i64, z: i64, dx: f64, dy: f64, dz: f64) -> f64 {
+        // 12 cube edge directions, the last 4 repeat some of them
+        return match hash(self.seed, x, y, z) & 15 {
+            0  | 12 =>  dx + dy,
+            1  | 13 => -dx + dy,
+            2       =>  dx - dy,
+            3       => -dx - dy,
+            4       =>  dx + dz,
+            5       => -dx + dz,
+            6       =>  dx - dz,
+            7       => -dx - dz,
+            8       =>  dy + dz,
+            9  | 14 => -dy + dz,
+            10      =>  dy - dz,
+            _       => -dy - dz,
+        };
+    }
+}
+
+fn hash(seed: u64, x: i64, y: i64, z: i64) -> u64 {
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+
+    // splitmix64 finalizer
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    return h ^ (h >> 31);
+}
+
+fn fade(t: f64) -> f64 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    return a + (b - a) * t;
+}
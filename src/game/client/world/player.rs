@@ -9,6 +9,11 @@ pub struct Player {
     pub position : Vector3<f32>,
 }
 
+impl Player {
+    /// How far away from the player blocks can be broken or placed.
+    pub const REACH: f32 = 6.0;
+}
+
 impl<'de> Deserialize<'de> for Player {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -0,0 +1,191 @@
+use cgmath::{Vector3, InnerSpace, vec3};
+
+use crate::graphics::{camera::Camera, utils::Side};
+
+use super::{chunk::chunk::BlockState, world::World};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub position : Vector3<i32>,
+    pub side     : Side,    // Face of the block the ray entered through
+    pub distance : f32,
+}
+
+/// First non-air block the camera is looking at, no further than `reach` blocks away.
+pub fn pick(world: &World, camera: &Camera, reach: f32) -> Option<RaycastHit> {
+    return raycast(world, camera.position, camera.direction(), reach);
+}
+
+/// Walks the block grid along the ray (Amanatides & Woo), visiting every block it passes through in order.
+/// Blocks in unloaded chunks count as air.
+pub fn raycast(world: &World, origin: Vector3<f32>, direction: Vector3<f32>, reach: f32) -> Option<RaycastHit> {
+    if direction.magnitude2() == 0.0 {
+        return None;
+    }
+
+    let direction = direction.normalize();
+    let mut position = vec3(origin.x.floor() as i32, origin.y.floor() as i32, origin.z.floor() as i32);
+
+    // Per axis: which way to step, ray length between two grid planes and ray length to the next plane
+    let mut step = [0; 3];
+    let mut delta = [f32::INFINITY; 3];
+    let mut next = [f32::INFINITY; 3];
+    for axis in 0 .. 3 {
+        let (o, d, cell) = (origin[axis], direction[axis], position[axis] as f32);
+        if d > 0.0 {
+            step[axis] = 1;
+            delta[axis] = 1.0 / d;
+            next[axis] = (cell + 1.0 - o) / d;
+        } else if d < 0.0 {
+            step[axis] = -1;
+            delta[axis] = -1.0 / d;
+            next[axis] = (cell - o) / d;
+        }
+    }
+
+    // Starting inside of a block hits the face looking back along the ray's main axis
+    let main_axis = (0 .. 3).max_by(|a, b| direction[*a].abs().total_cmp(&direction[*b].abs())).unwrap();
+    let mut side = entered_side(main_axis, step[main_axis]);
+    let mut distance = 0.0;
+    while distance <= reach {
+        if world.get_block(position).is_some_and(|block| block != BlockState::AIR) {
+            return Some(RaycastHit { position, side, distance });
+        }
+
+        let axis = if next[0] < next[1] {
+            if next[0] < next[2] { 0 } else { 2 }
+        } else if next[1] < next[2] { 1 } else { 2 };
+
+        position[axis] += step[axis];
+        distance = next[axis];
+        next[axis] += delta[axis];
+        side = entered_side(axis, step[axis]);
+    }
+
+    return None;
+}
+
+// Face a ray moving along `axis` in the direction of `step` enters a block through
+fn entered_side(axis: usize, step: i32) -> Side {
+    return match (axis, step > 0) {
+        (0, true)  => Side::Back,
+        (0, false) => Side::Front,
+        (1, true)  => Side::Bottom,
+        (1, false) => Side::Top,
+        (_, true)  => Side::Left,
+        (_, false) => Side::Right,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Deg;
+
+    use super::*;
+    use super::super::chunk::chunk::Chunk;
+
+    const STONE: BlockState = BlockState(1);
+
+    // Empty chunks around the origin with the given blocks set
+    fn world(blocks: &[Vector3<i32>]) -> World {
+        let mut world = World::new();
+        for x in -2 ..= 1 {
+            for y in -2 ..= 1 {
+                for z in -2 ..= 1 {
+                    world.insert(vec3(x, y, z), Chunk::new());
+                }
+            }
+        }
+
+        for block in blocks {
+            assert!(world.set_block(*block, STONE));
+        }
+
+        return world;
+    }
+
+    fn assert_hit(hit: Option<RaycastHit>, position: Vector3<i32>, side: Side, distance: f32) {
+        let hit = hit.expect("Ray didn't hit anything");
+        assert_eq!(hit.position, position);
+        assert_eq!(hit.side, side);
+        assert!((hit.distance - distance).abs() < 1e-4, "Hit at distance {}, expected {}", hit.distance, distance);
+    }
+
+    #[test]
+    fn axis_aligned_rays() {
+        let world = world(&[vec3(5, 0, 0), vec3(0, -3, 0), vec3(0, 0, 7)]);
+        let origin = vec3(0.5, 0.5, 0.5);
+        assert_hit(raycast(&world, origin, vec3(1.0, 0.0, 0.0), 10.0), vec3(5, 0, 0), Side::Back, 4.5);
+        assert_hit(raycast(&world, origin, vec3(0.0, -2.0, 0.0), 10.0), vec3(0, -3, 0), Side::Top, 2.5);
+        assert_hit(raycast(&world, origin, vec3(0.0, 0.0, 1.0), 10.0), vec3(0, 0, 7), Side::Left, 6.5);
+        assert!(raycast(&world, origin, vec3(-1.0, 0.0, 0.0), 10.0).is_none());
+        assert!(raycast(&world, origin, vec3(0.0, 0.0, 0.0), 10.0).is_none());
+    }
+
+    #[test]
+    fn face_normals_point_back_at_the_ray() {
+        let directions = [vec3(1, 0, 0), vec3(-1, 0, 0), vec3(0, 1, 0), vec3(0, -1, 0), vec3(0, 0, 1), vec3(0, 0, -1)];
+        let world = world(&directions.map(|direction| direction * 3));
+        for direction in directions {
+            let hit = raycast(&world, vec3(0.5, 0.5, 0.5), direction.cast::<f32>().unwrap(), 10.0).unwrap();
+            let (x, y, z) = hit.side.normal();
+            assert_eq!(vec3(x as i32, y as i32, z as i32), -direction);
+            assert_eq!(hit.position, direction * 3);
+        }
+    }
+
+    #[test]
+    fn diagonal_rays() {
+        // Walls across the ray, the ray crosses them between the other axis' planes
+        let x_wall: Vec<_> = (-8 .. 8).map(|z| vec3(4, 0, z)).collect();
+        let world = world(&x_wall);
+        assert_hit(raycast(&world, vec3(0.5, 0.5, 0.25), vec3(1.0, 0.0, 1.0), 10.0), vec3(4, 0, 3), Side::Back, 3.5 * 2f32.sqrt());
+
+        let z_wall: Vec<_> = (-8 .. 8).map(|x| vec3(x, 0, 4)).collect();
+        let world = self::world(&z_wall);
+        assert_hit(raycast(&world, vec3(0.25, 0.5, 0.5), vec3(1.0, 0.0, 1.0), 10.0), vec3(3, 0, 4), Side::Left, 3.5 * 2f32.sqrt());
+
+        // Blocks right next to the ray's path don't stop it
+        let world = self::world(&[vec3(1, 0, 0), vec3(0, 1, 0), vec3(1, 1, 0), vec3(2, 1, 1), vec3(2, 2, 2)]);
+        let hit = raycast(&world, vec3(0.5, 0.5, 0.5), vec3(1.0, 1.1, 1.2), 10.0).unwrap();
+        assert_eq!(hit.position, vec3(2, 2, 2));
+    }
+
+    #[test]
+    fn negative_coordinates_across_chunk_borders() {
+        let world = world(&[vec3(-40, 10, -31)]);
+        assert_hit(raycast(&world, vec3(-0.5, 10.5, -30.5), vec3(-1.0, 0.0, 0.0), 50.0), vec3(-40, 10, -31), Side::Front, 38.5);
+
+        // Goes from chunk (0, 0, 0) into (-1, -1, -1)
+        let wall: Vec<_> = (-5 .. 2).flat_map(|y| (-5 .. 2).map(move |z| vec3(-5, y, z))).collect();
+        let world = self::world(&wall);
+        let direction = vec3(-1.0, -0.5, -0.5);
+        assert_hit(raycast(&world, vec3(1.5, 1.5, 1.5), direction, 10.0), vec3(-5, -2, -2), Side::Front, 5.5 * direction.magnitude());
+    }
+
+    #[test]
+    fn reach_cuts_the_ray_off() {
+        let world = world(&[vec3(5, 0, 0)]);
+        let origin = vec3(0.5, 0.5, 0.5);
+        let direction = vec3(1.0, 0.0, 0.0);
+        assert!(raycast(&world, origin, direction, 4.4).is_none());
+        assert_hit(raycast(&world, origin, direction, 4.5), vec3(5, 0, 0), Side::Back, 4.5);
+
+        // Starting inside of a block hits it right away
+        assert_hit(raycast(&world, vec3(5.5, 0.5, 0.5), vec3(0.0, 0.0, -1.0), 0.0), vec3(5, 0, 0), Side::Right, 0.0);
+    }
+
+    #[test]
+    fn unloaded_chunks_are_air() {
+        let mut world = world(&[vec3(40, 0, 0)]);
+        world.remove(vec3(1, 0, 0));
+        assert!(raycast(&world, vec3(0.5, 0.5, 0.5), vec3(1.0, 0.0, 0.0), 100.0).is_none());
+    }
+
+    #[test]
+    fn pick_looks_along_the_camera() {
+        let world = world(&[vec3(5, 0, 0), vec3(0, 5, 0)]);
+        assert_hit(pick(&world, &Camera::new((0.5, 0.5, 0.5), Deg(0.0), Deg(0.0)), 10.0), vec3(5, 0, 0), Side::Back, 4.5);
+        assert_hit(pick(&world, &Camera::new((0.5, 0.5, 0.5), Deg(0.0), Deg(90.0)), 10.0), vec3(0, 5, 0), Side::Bottom, 4.5);
+    }
+}
@@ -0,0 +1,150 @@
+use cgmath::Vector3;
+
+use super::{chunk::chunk::{Chunk, BlockState, CHUNK_SIZE}, noise::Noise, world::World};
+
+/// Produces the initial contents of chunks.
+/// Implementations must be deterministic: the same seed and chunk position always give the same chunk,
+/// that's what keeps every server restart and every client in agreement about the world.
+pub trait TerrainGenerator: Send + Sync {
+    fn generate(&self, position: Vector3<i32>) -> Chunk;
+
+    /// Height of the first air block above the topmost solid block of a column, used to find spawn points.
+    fn height(&self, x: i32, z: i32) -> i32;
+}
+
+/// Rolling hills from a 2D heightmap, carved and extended with 3D density noise to get overhangs.
+pub struct NoiseTerrainGenerator {
+    pub seed    : u64,
+    pub surface : BlockState,
+    pub filler  : BlockState,
+
+    heightmap   : Noise,
+    density     : Noise,
+}
+
+impl NoiseTerrainGenerator {
+    const BASE_HEIGHT       : f64 = 0.0;
+    const HEIGHT_AMPLITUDE  : f64 = 24.0;
+    const HEIGHT_SCALE      : f64 = 1.0 / 128.0;
+    const DENSITY_AMPLITUDE : f64 = 10.0;
+    const DENSITY_SCALE     : f64 = 1.0 / 24.0;
+
+    pub fn new(seed: u64, surface: BlockState, filler: BlockState) -> Self {
+        return Self {
+            seed,
+            surface,
+            filler,
+
+            // Separate streams so heightmap and density features don't line up
+            heightmap : Noise::new(seed),
+            density   : Noise::new(seed ^ 0x5DEE_CE66_D1CE_4E5B),
+        };
+    }
+
+    fn column_height(&self, x: i32, z: i32) -> f64 {
+        let noise = self.heightmap.fbm2(x as f64 * Self::HEIGHT_SCALE, z as f64 * Self::HEIGHT_SCALE, 4);
+        return Self::BASE_HEIGHT + noise * Self::HEIGHT_AMPLITUDE;
+    }
+
+    // Positive inside of the terrain
+    fn density(&self, height: f64, x: i32, y: i32, z: i32) -> f64 {
+        let scale = Self::DENSITY_SCALE;
+        let noise = self.density.fbm3(x as f64 * scale, y as f64 * scale, z as f64 * scale, 3);
+        return (height - y as f64) + noise * Self::DENSITY_AMPLITUDE;
+    }
+}
+
+impl TerrainGenerator for NoiseTerrainGenerator {
+    fn generate(&self, position: Vector3<i32>) -> Chunk {
+        let origin = World::origin(position);
+        let mut chunk = Chunk::new();
+        for i in 0 .. CHUNK_SIZE {
+            for k in 0 .. CHUNK_SIZE {
+                let (x, z) = (origin.x + i as i32, origin.z + k as i32);
+                let height = self.column_height(x, z);
+
+                // Walk down from one block above the chunk so the top layer knows whether it's exposed
+                let mut above_solid = self.density(height, x, origin.y + CHUNK_SIZE as i32, z) > 0.0;
+                for j in (0 .. CHUNK_SIZE).rev() {
+                    let solid = self.density(height, x, origin.y + j as i32, z) > 0.0;
+                    if solid {
+                        chunk.set(i, j, k, if above_solid { self.filler } else { self.surface });
+                    }
+
+                    above_solid = solid;
+                }
+            }
+        }
+
+        return chunk;
+    }
+
+    fn height(&self, x: i32, z: i32) -> i32 {
+        // Density noise can only move the surface this far away from the heightmap
+        let height = self.column_height(x, z);
+        let top = (height + Self::DENSITY_AMPLITUDE).ceil() as i32;
+        let bottom = (height - Self::DENSITY_AMPLITUDE).floor() as i32;
+        for y in (bottom ..= top).rev() {
+            if self.density(height, x, y, z) > 0.0 {
+                return y + 1;
+            }
+        }
+
+        return bottom;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::vec3;
+
+    use super::*;
+    use super::super::chunk::chunk::CHUNK_VOLUME;
+
+    const SURFACE: BlockState = BlockState(1);
+    const FILLER: BlockState = BlockState(2);
+
+    // Chunks around the surface, where both the heightmap and the density noise matter
+    const POSITIONS: [Vector3<i32>; 4] = [vec3(0, 0, 0), vec3(0, -1, 0), vec3(-3, 0, 7), vec3(1000, -1, -1000)];
+
+    fn blocks(chunk: &Chunk) -> Vec<BlockState> {
+        return (0 .. CHUNK_VOLUME).map(|i| chunk.blocks.get(i)).collect();
+    }
+
+    #[test]
+    fn same_seed_gives_identical_chunks() {
+        let a = NoiseTerrainGenerator::new(1234, SURFACE, FILLER);
+        let b = NoiseTerrainGenerator::new(1234, SURFACE, FILLER);
+        for position in POSITIONS {
+            let (first, second) = (a.generate(position), b.generate(position));
+            assert_eq!(bincode::serialize(&first).unwrap(), bincode::serialize(&second).unwrap());
+
+            // Generating again in another order doesn't change anything either
+            assert_eq!(blocks(&a.generate(position)), blocks(&first));
+        }
+    }
+
+    #[test]
+    fn different_seed_gives_different_chunks() {
+        let a = NoiseTerrainGenerator::new(1234, SURFACE, FILLER);
+        let b = NoiseTerrainGenerator::new(4321, SURFACE, FILLER);
+        for position in POSITIONS {
+            assert_ne!(blocks(&a.generate(position)), blocks(&b.generate(position)));
+        }
+    }
+
+    #[test]
+    fn height_matches_generated_chunks() {
+        let generator = NoiseTerrainGenerator::new(99, SURFACE, FILLER);
+        for (x, z) in [(0, 0), (5, 17), (-40, 3), (123, -77)] {
+            let height = generator.height(x, z);
+            let block = |y: i32| {
+                let (chunk_position, (i, j, k)) = World::split(vec3(x, y, z));
+                return generator.generate(chunk_position).get(i, j, k);
+            };
+
+            assert_eq!(block(height), BlockState::AIR);
+            assert_eq!(block(height - 1), SURFACE);
+        }
+    }
+}
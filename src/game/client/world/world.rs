@@ -0,0 +1,331 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+
+use cgmath::{Vector3, vec3};
+
+use super::{chunk::chunk::{Chunk, BlockState, PaddedChunk, CHUNK_SIZE}, light::{Light, LightMap}, light_worker::{LightWorker, LightJob}, block_registry::BlockRegistry};
+
+/// Sparse collection of chunks addressed by integer chunk coordinates.
+/// Chunk `(x, y, z)` covers blocks `[x * CHUNK_SIZE, (x + 1) * CHUNK_SIZE)` on every axis.
+pub struct World {
+    pub chunks : HashMap<Vector3<i32>, Chunk>,
+
+    // Chunks whose meshes are out of date, either because they changed
+    // or because something at the border of a neighbour did
+    pub dirty    : HashSet<Vector3<i32>>,
+
+    // Only worlds that get rendered need light, it's computed on a worker thread and copied into `light`
+    lighting     : Option<LightWorker>,
+    light        : HashMap<Vector3<i32>, LightMap>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        return Self {
+            chunks   : HashMap::new(),
+            dirty    : HashSet::new(),
+            lighting : None,
+            light    : HashMap::new(),
+        };
+    }
+
+    pub fn with_lighting(registry: Arc<BlockRegistry>) -> Self {
+        return Self {
+            lighting: Some(LightWorker::new(registry)),
+            ..Self::new()
+        };
+    }
+
+    /// In lit worlds the chunk and its neighbours get remeshed once its light arrives, see `update_light`.
+    pub fn insert(&mut self, position: Vector3<i32>, chunk: Chunk) {
+        match &mut self.lighting {
+            Some(lighting) => lighting.submit(LightJob::Load(position, chunk.clone())),
+            None => self.mark_neighbours_dirty(position),
+        }
+
+        self.chunks.insert(position, chunk);
+    }
+
+    pub fn remove(&mut self, position: Vector3<i32>) -> Option<Chunk> {
+        let chunk = self.chunks.remove(&position);
+        if chunk.is_some() {
+            self.mark_neighbours_dirty(position);
+            self.light.remove(&position);
+            if let Some(lighting) = &mut self.lighting {
+                lighting.submit(LightJob::Remove(position));
+            }
+        }
+
+        return chunk;
+    }
+
+    /// Takes over the light computed since the last call and marks the chunks it changed dirty.
+    pub fn update_light(&mut self) {
+        let lighting = match &mut self.lighting {
+            Some(lighting) => lighting,
+            None => return,
+        };
+
+        while let Some(update) = lighting.poll() {
+            for (position, map) in update.maps {
+                // Light of chunks that were unloaded in the meantime is out of date
+                if self.chunks.contains_key(&position) {
+                    self.light.insert(position, map);
+                    self.dirty.insert(position);
+                }
+            }
+        }
+    }
+
+    pub fn chunk(&self, position: Vector3<i32>) -> Option<&Chunk> {
+        return self.chunks.get(&position);
+    }
+
+    pub fn chunk_mut(&mut self, position: Vector3<i32>) -> Option<&mut Chunk> {
+        return self.chunks.get_mut(&position);
+    }
+
+    /// Returns `None` if the chunk containing the block isn't loaded.
+    pub fn get_block(&self, position: Vector3<i32>) -> Option<BlockState> {
+        let (chunk_position, (x, y, z)) = Self::split(position);
+        return self.chunks.get(&chunk_position).map(|chunk| chunk.get(x, y, z));
+    }
+
+    /// Returns `false` if the chunk containing the block isn't loaded.
+    pub fn set_block(&mut self, position: Vector3<i32>, block_state: BlockState) -> bool {
+        let (chunk_position, (x, y, z)) = Self::split(position);
+        if let Some(chunk) = self.chunks.get_mut(&chunk_position) {
+            chunk.set(x, y, z, block_state);
+            self.mark_block_dirty(position);
+
+            if let Some(lighting) = &mut self.lighting {
+                lighting.submit(LightJob::SetBlock(position, block_state));
+            }
+
+            return true;
+        }
+
+        return false;
+    }
+
+    /// Returns `None` if the chunk containing the block isn't loaded or lit yet, worlds without lighting are fully lit.
+    pub fn get_light(&self, position: Vector3<i32>) -> Option<Light> {
+        let (chunk_position, (x, y, z)) = Self::split(position);
+        if self.lighting.is_none() {
+            return self.chunks.get(&chunk_position).map(|_| Light::FULL);
+        }
+
+        return self.light.get(&chunk_position).map(|map| map.get(x, y, z));
+    }
+
+    /// Drains the set of chunks that need to be remeshed.
+    pub fn take_dirty(&mut self) -> Vec<Vector3<i32>> {
+        return self.dirty.drain().collect();
+    }
+
+    /// Copies the chunk together with a one block border from its 26 neighbours.
+    /// Blocks of unloaded neighbours are treated as air under open sky.
+    /// Returns `None` if the chunk isn't loaded, or in lit worlds if its light didn't arrive yet.
+    pub fn padded(&self, position: Vector3<i32>) -> Option<PaddedChunk> {
+        self.chunks.get(&position)?;
+        if self.lighting.is_some() && !self.light.contains_key(&position) {
+            return None;
+        }
+
+        // Range of padded coordinates each neighbour covers along an axis, and the local coordinate of its first block
+        let size = CHUNK_SIZE as isize;
+        let span = |offset: i32| match offset {
+            -1 => (-1 ..= -1, size - 1),
+            0  => (0 ..= size - 1, 0),
+            _  => (size ..= size, 0),
+        };
+
+        // Each neighbour is looked up once and its slice copied over, going through `get_block` for every block is a lot slower
+        let mut padded = PaddedChunk::new();
+        for dz in -1 ..= 1 {
+            for dy in -1 ..= 1 {
+                for dx in -1 ..= 1 {
+                    let neighbour = position + vec3(dx, dy, dz);
+                    let chunk = match self.chunks.get(&neighbour) {
+                        Some(chunk) => chunk,
+                        None => continue,
+                    };
+
+                    let light = self.light.get(&neighbour);
+                    let ((xs, x0), (ys, y0), (zs, z0)) = (span(dx), span(dy), span(dz));
+                    for k in zs.clone() {
+                        for j in ys.clone() {
+                            for i in xs.clone() {
+                                let (x, y, z) = ((i - xs.start() + x0) as usize, (j - ys.start() + y0) as usize, (k - zs.start() + z0) as usize);
+                                padded.set(i, j, k, chunk.get(x, y, z));
+                                if let Some(light) = light {
+                                    padded.set_light(i, j, k, light.get(x, y, z));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        return Some(padded);
+    }
+
+    /// Converts a global block position into a chunk position and a local block position.
+    pub fn split(position: Vector3<i32>) -> (Vector3<i32>, (usize, usize, usize)) {
+        let size = CHUNK_SIZE as i32;
+        let chunk_position = vec3(position.x.div_euclid(size), position.y.div_euclid(size), position.z.div_euclid(size));
+        let local = (position.x.rem_euclid(size) as usize,
+                     position.y.rem_euclid(size) as usize,
+                     position.z.rem_euclid(size) as usize);
+
+        return (chunk_position, local);
+    }
+
+    /// Chunk containing a point in world space.
+    pub fn chunk_at(position: Vector3<f32>) -> Vector3<i32> {
+        let block = vec3(position.x.floor() as i32, position.y.floor() as i32, position.z.floor() as i32);
+        return Self::split(block).0;
+    }
+
+    /// World-space position of the chunk's `(0, 0, 0)` block.
+    pub fn origin(chunk_position: Vector3<i32>) -> Vector3<i32> {
+        return chunk_position * CHUNK_SIZE as i32;
+    }
+
+    /// Chunks whose padded view contains the block, loaded or not.
+    pub fn sharing(position: Vector3<i32>) -> Vec<Vector3<i32>> {
+        let (chunk_position, (x, y, z)) = Self::split(position);
+        let offsets = |local: usize| -> &'static [i32] {
+            if local == 0 { &[0, -1] } else if local == CHUNK_SIZE - 1 { &[0, 1] } else { &[0] }
+        };
+
+        let mut chunks = vec![];
+        for dx in offsets(x) {
+            for dy in offsets(y) {
+                for dz in offsets(z) {
+                    chunks.push(chunk_position + vec3(*dx, *dy, *dz));
+                }
+            }
+        }
+
+        return chunks;
+    }
+
+    fn mark_block_dirty(&mut self, position: Vector3<i32>) {
+        for chunk_position in Self::sharing(position) {
+            if self.chunks.contains_key(&chunk_position) {
+                self.dirty.insert(chunk_position);
+            }
+        }
+    }
+
+    fn mark_neighbours_dirty(&mut self, position: Vector3<i32>) {
+        for dx in -1 ..= 1 {
+            for dy in -1 ..= 1 {
+                for dz in -1 ..= 1 {
+                    let neighbour = position + vec3(dx, dy, dz);
+                    if self.chunks.contains_key(&neighbour) {
+                        self.dirty.insert(neighbour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn registry() -> Arc<BlockRegistry> {
+        return Arc::new(BlockRegistry::from_ron(r#"#![enable(implicit_some)] [
+            (id: 1, name: "stone", textures: (all: "test")),
+            (id: 2, name: "lamp",  textures: (all: "test"), light: 14),
+        ]"#).unwrap());
+    }
+
+    // Chunk with a bit of everything, different for every position
+    fn chunk(position: Vector3<i32>) -> Chunk {
+        let mut chunk = Chunk::new();
+        for x in 0 .. CHUNK_SIZE {
+            for y in 0 .. CHUNK_SIZE {
+                for z in 0 .. CHUNK_SIZE {
+                    let value = x * 3 + y * 5 + z * 7 + (position.x * 11 + position.y * 13 + position.z * 17).unsigned_abs() as usize;
+                    chunk.set(x, y, z, BlockState([0, 0, 0, 1, 2][value % 5]));
+                }
+            }
+        }
+
+        return chunk;
+    }
+
+    // Waits for the light worker to catch up with everything submitted so far
+    fn wait_for_light(world: &mut World) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !world.chunks.keys().all(|position| world.light.contains_key(position)) {
+            assert!(Instant::now() < deadline, "Light never arrived");
+            world.update_light();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn assert_padded_matches(world: &World, position: Vector3<i32>) {
+        let padded = world.padded(position).unwrap();
+        let origin = World::origin(position);
+        let size = CHUNK_SIZE as isize;
+        for k in -1 ..= size {
+            for j in -1 ..= size {
+                for i in -1 ..= size {
+                    let block = origin + vec3(i as i32, j as i32, k as i32);
+                    assert_eq!(padded.get(i, j, k), world.get_block(block).unwrap_or(BlockState::AIR), "Block {:?}", block);
+                    assert_eq!(padded.light(i, j, k), world.get_light(block).unwrap_or(Light::FULL), "Light of {:?}", block);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn padded_chunks_without_lighting() {
+        let mut world = World::new();
+        for position in [vec3(0, 0, 0), vec3(-1, 0, 0), vec3(0, -1, 1), vec3(1, 1, 1)] {
+            world.insert(position, chunk(position));
+        }
+
+        assert_padded_matches(&world, vec3(0, 0, 0));
+        assert_padded_matches(&world, vec3(-1, 0, 0));
+        assert!(world.padded(vec3(5, 5, 5)).is_none());
+    }
+
+    #[test]
+    fn padded_chunks_with_lighting() {
+        let mut world = World::with_lighting(registry());
+        for x in -1 ..= 0 {
+            for y in -1 ..= 0 {
+                for z in -1 ..= 1 {
+                    world.insert(vec3(x, y, z), chunk(vec3(x, y, z)));
+                }
+            }
+        }
+
+        // Nothing to mesh before the light is there
+        assert!(world.take_dirty().is_empty());
+        assert!(world.padded(vec3(0, 0, 0)).is_none());
+
+        wait_for_light(&mut world);
+        assert!(world.take_dirty().contains(&vec3(-1, -1, 0)));
+        assert_padded_matches(&world, vec3(0, 0, 0));
+        assert_padded_matches(&world, vec3(-1, -1, 1));
+
+        world.remove(vec3(0, 0, 1));
+        assert!(world.get_light(vec3(5, 5, 40)).is_none());
+        assert_padded_matches(&world, vec3(0, 0, 0));
+    }
+}
@@ -1,2 +1,3 @@
 pub mod client;
-pub mod net;
\ No newline at end of file
+pub mod net;
+pub mod server;
@@ -1 +1,2 @@
-pub mod proto;
\ No newline at end of file
+pub mod proto;
+pub mod transport;
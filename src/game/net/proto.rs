@@ -1,28 +1,95 @@
+use std::{collections::HashMap, fmt};
+
 use cgmath::Vector3;
+use instant::Duration;
 use serde::{Serialize, Deserialize};
 use uuid::Uuid as UUID;
 
-use crate::game::client::world::player::Player;
+use crate::game::client::world::{player::Player, chunk::chunk::BlockState};
+
+/// Bumped on every change to the packets, clients only join servers with the same version.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// How often both sides send `KeepAlive`s, so silence means the other side is gone.
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What clients need to know about the world before joining it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorldInfo {
+    pub seed  : u64,
+    pub spawn : Vector3<f32>,
+}
+
+/// Why the server refused to let a player join.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum JoinRejection {
+    VersionMismatch {
+        server : u32,
+        client : u32,
+    },
+    NameTaken,
+    ServerFull,
+}
+
+impl fmt::Display for JoinRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            JoinRejection::VersionMismatch { server, client } => write!(f, "protocol version {} doesn't match the server's {}", client, server),
+            JoinRejection::NameTaken => write!(f, "name is already taken"),
+            JoinRejection::ServerFull => write!(f, "server is full"),
+        };
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientPacket {
-    QueryPlayerList,
-    PlayerJoin {
-        name     : String,
+    /// First packet of every client, answered with either `JoinAccepted` or `JoinRejected`.
+    Connect {
+        protocol_version : u32,
+        name             : String,
     },
     PlayerLeave {
         token    : UUID,
         uuid     : UUID,
     },
+    KeepAlive {
+        token    : UUID,
+        uuid     : UUID,
+    },
     PlayerMove {
         token    : UUID,
         uuid     : UUID,
         position : Vector3<f32>,
     },
+    BreakBlock {
+        token    : UUID,
+        uuid     : UUID,
+        position : Vector3<i32>,
+    },
+    PlaceBlock {
+        token    : UUID,
+        uuid     : UUID,
+        position : Vector3<i32>,
+        block    : BlockState,
+    },
+    RequestChunks {
+        token     : UUID,
+        uuid      : UUID,
+        positions : Vec<Vector3<i32>>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerPacket {
+    JoinAccepted {
+        uuid     : UUID,
+        token    : UUID, // Has to be sent along with every packet about the player
+        world    : WorldInfo,
+        players  : HashMap<UUID, Player>, // Everyone else on the server
+    },
+    JoinRejected {
+        reason   : JoinRejection,
+    },
     PlayerJoin {
         uuid     : UUID,
         player   : Player,
@@ -34,4 +101,13 @@ pub enum ServerPacket {
         uuid     : UUID,
         position : Vector3<f32>,
     },
+    BlockChange {
+        position : Vector3<i32>,
+        block    : BlockState,
+    },
+    ChunkData {
+        position : Vector3<i32>,
+        data     : Vec<u8>, // `Chunk::compress`ed
+    },
+    KeepAlive,
 }
\ No newline at end of file
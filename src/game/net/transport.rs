@@ -0,0 +1,849 @@
+use std::{collections::{HashMap, VecDeque, BTreeMap}, net::{UdpSocket, SocketAddr, ToSocketAddrs}, io::ErrorKind, mem};
+
+use anyhow::{Result, bail};
+use instant::{Instant, Duration};
+use log::error;
+use serde::{Serialize, Deserialize};
+
+/// Datagrams never get larger than this, so they fit into a single IP packet on pretty much any network.
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+
+/// Messages larger than this are split into fragments of this size.
+pub const FRAGMENT_SIZE: usize = 1024;
+
+/// Largest message that can be sent, peers refuse to reassemble anything larger.
+pub const MAX_MESSAGE_SIZE: usize = 1024 * FRAGMENT_SIZE;
+
+/// Fragments of unreliable messages are dropped if the rest doesn't arrive in time.
+pub const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Lower bound of the resend timeout, the actual one follows the round trip time.
+pub const MIN_RESEND_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Reliable messages in flight at once. Receivers only buffer this many ahead of the next one they deliver.
+pub const RELIABLE_WINDOW: u16 = 512;
+
+/// Unreliable messages reassembled at the same time, fragments of any others are dropped.
+pub const MAX_REASSEMBLIES: usize = 4;
+
+// Datagrams acknowledged by each datagram: the latest one received and 32 before it
+const ACK_WINDOW: u16 = 33;
+
+// Bincode overhead of the datagram header and of each message at most: enum tag, ids and payload length
+const DATAGRAM_OVERHEAD: usize = 4 + 2 + 2 + 2 + 4 + 2 + 8;
+const MESSAGE_OVERHEAD: usize = 4 + 2 + 2 + 2 + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Might get lost, duplicated or arrive out of order. For state that's sent over and over again, like movement.
+    Unreliable,
+
+    /// Resent until acknowledged and delivered in the order it was sent in.
+    Reliable,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Datagram {
+    session  : u32, // Random per connection, changes when the peer restarts
+    base     : u16, // Oldest reliable message the sender still waits on an acknowledgement for
+    sequence : u16,
+    ack      : u16, // Latest datagram received from the peer
+    ack_bits : u32, // Bit `n` is set if datagram `ack - n - 1` was received too
+    expected : u16, // Every reliable message before this one was delivered, covers acks outside of the ack bits
+    messages : Vec<Message>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum Message {
+    Unreliable(Vec<u8>),
+
+    // Piece of an unreliable message too large for a single datagram
+    Fragment {
+        group   : u16,
+        index   : u16,
+        count   : u16,
+        payload : Vec<u8>,
+    },
+
+    // Large reliable messages are sent as consecutive ones, all but the final piece having `last` unset
+    Reliable {
+        id      : u16,
+        last    : bool,
+        payload : Vec<u8>,
+    },
+}
+
+impl Message {
+    fn payload(&self) -> &[u8] {
+        return match self {
+            Message::Unreliable(payload) => payload,
+            Message::Fragment { payload, .. } => payload,
+            Message::Reliable { payload, .. } => payload,
+        };
+    }
+}
+
+struct Outgoing {
+    id        : u16,
+    last      : bool,
+    payload   : Vec<u8>,
+    last_sent : Option<Instant>,
+}
+
+// Fragments of an unreliable message received so far
+struct Reassembly {
+    started   : Instant,
+    remaining : usize,
+    pieces    : Vec<Option<Vec<u8>>>,
+}
+
+struct InFlight {
+    sent     : Instant,
+    messages : Vec<u16>, // Ids of the reliable messages in the datagram
+}
+
+/// Sequencing, acknowledgement and resending of messages exchanged with a single peer.
+/// Doesn't do any IO itself: datagrams from the peer go into `receive` and the ones `poll` returns have to be sent to it.
+pub struct Connection {
+    session         : u32,
+    remote_session  : Option<u32>, // Unknown until the first datagram from the peer
+    local_sequence  : u16, // Of the next datagram sent
+    remote_sequence : u16, // Latest datagram received
+    received        : u32, // Ack bits relative to `remote_sequence`
+    ack_pending     : bool,
+    in_flight       : HashMap<u16, InFlight>,
+    rtt             : Duration,
+    last_received   : Option<Instant>,
+
+    unreliable      : Vec<Message>,
+    reliable        : VecDeque<Outgoing>, // Sent but not yet acknowledged
+    next_id         : u16,
+    next_group      : u16,
+
+    next_expected   : u16, // Id of the next reliable message to deliver
+    out_of_order    : BTreeMap<u16, (bool, Vec<u8>)>,
+    partial         : Vec<u8>, // Pieces of a fragmented reliable message delivered so far
+    fragments       : HashMap<u16, Reassembly>,
+    inbox           : VecDeque<Vec<u8>>,
+}
+
+/// Whether sequence number `a` comes after `b`, taking wrap around into account.
+pub fn sequence_greater(a: u16, b: u16) -> bool {
+    return a != b && a.wrapping_sub(b) < 0x8000;
+}
+
+impl Connection {
+    pub fn new() -> Self {
+        return Self {
+            session         : rand::random(),
+            remote_session  : None,
+            local_sequence  : 0,
+            remote_sequence : u16::MAX,
+            received        : 0,
+            ack_pending     : false,
+            in_flight       : HashMap::new(),
+            rtt             : Duration::from_millis(100),
+            last_received   : None,
+
+            unreliable      : vec![],
+            reliable        : VecDeque::new(),
+            next_id         : 0,
+            next_group      : 0,
+
+            next_expected   : 0,
+            out_of_order    : BTreeMap::new(),
+            partial         : vec![],
+            fragments       : HashMap::new(),
+            inbox           : VecDeque::new(),
+        };
+    }
+
+    /// Queues a message, it goes out with the next `poll`.
+    /// Panics if the message is larger than `MAX_MESSAGE_SIZE`.
+    pub fn send(&mut self, channel: Channel, payload: Vec<u8>) {
+        assert!(payload.len() <= MAX_MESSAGE_SIZE, "Message of {} bytes is too large to send", payload.len());
+
+        // Empty messages still need a piece
+        let pieces: Vec<&[u8]> = if payload.is_empty() { vec![&[]] } else { payload.chunks(FRAGMENT_SIZE).collect() };
+        match channel {
+            Channel::Unreliable if pieces.len() == 1 => self.unreliable.push(Message::Unreliable(payload)),
+            Channel::Unreliable => {
+                for (index, piece) in pieces.iter().enumerate() {
+                    self.unreliable.push(Message::Fragment {
+                        group   : self.next_group,
+                        index   : index as u16,
+                        count   : pieces.len() as u16,
+                        payload : piece.to_vec(),
+                    });
+                }
+
+                self.next_group = self.next_group.wrapping_add(1);
+            }
+
+            Channel::Reliable => {
+                for (index, piece) in pieces.iter().enumerate() {
+                    self.reliable.push_back(Outgoing {
+                        id        : self.next_id,
+                        last      : index == pieces.len() - 1,
+                        payload   : piece.to_vec(),
+                        last_sent : None,
+                    });
+
+                    self.next_id = self.next_id.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Handles a datagram from the peer, the messages it completes can be taken with `next_message`.
+    /// A peer that restarted starts over with a new session, the connection then starts over as well
+    /// and whatever was still queued for the peer is dropped.
+    pub fn receive(&mut self, bytes: &[u8], now: Instant) -> Result<()> {
+        let datagram: Datagram = bincode::deserialize(bytes)?;
+
+        // Reliable messages of a session have to be delivered from the first one on, or the rest never would be
+        if self.remote_session != Some(datagram.session) {
+            if datagram.base != 0 {
+                bail!("Datagram continues a session that was never started");
+            }
+
+            if self.remote_session.is_some() {
+                *self = Self::new();
+            }
+
+            self.remote_session = Some(datagram.session);
+        }
+
+        // Duplicates are dropped, they'd deliver unreliable messages twice
+        if sequence_greater(datagram.sequence, self.remote_sequence) {
+            let shift = datagram.sequence.wrapping_sub(self.remote_sequence) as u32;
+            self.received = if shift > 32 { 0 } else { (self.received << 1 | 1) << (shift - 1) };
+            self.remote_sequence = datagram.sequence;
+        } else {
+            let bit = self.remote_sequence.wrapping_sub(datagram.sequence) as u32;
+            if bit == 0 || bit > 32 || self.received & 1 << (bit - 1) != 0 {
+                return Ok(());
+            }
+
+            self.received |= 1 << (bit - 1);
+        }
+
+        self.last_received = Some(now);
+        self.acknowledge(datagram.ack, datagram.ack_bits, now);
+        self.reliable.retain(|outgoing| !sequence_greater(datagram.expected, outgoing.id));
+
+        // Datagrams carrying only acks aren't acknowledged, the peers would never stop otherwise
+        if !datagram.messages.is_empty() {
+            self.ack_pending = true;
+        }
+
+        self.fragments.retain(|_, reassembly| now - reassembly.started < FRAGMENT_TIMEOUT);
+        for message in datagram.messages {
+            match message {
+                Message::Unreliable(payload) => self.inbox.push_back(payload),
+                Message::Fragment { group, index, count, payload } => {
+                    // The datagram gets acknowledged regardless, so the messages after a bad fragment still have to be handled
+                    if let Err(error) = self.reassemble(group, index, count, payload, now) {
+                        error!("Invalid fragment: {}", error);
+                    }
+                }
+                Message::Reliable { id, last, payload } => {
+                    // Anything further ahead is more than the peer is allowed to have in flight
+                    if id.wrapping_sub(self.next_expected) < RELIABLE_WINDOW {
+                        self.out_of_order.insert(id, (last, payload));
+                    }
+                }
+            }
+        }
+
+        while let Some((last, payload)) = self.out_of_order.remove(&self.next_expected) {
+            self.next_expected = self.next_expected.wrapping_add(1);
+            if self.partial.len() + payload.len() > MAX_MESSAGE_SIZE {
+                self.partial.clear();
+                bail!("Reliable message larger than {} bytes", MAX_MESSAGE_SIZE);
+            }
+
+            self.partial.extend(payload);
+            if last {
+                self.inbox.push_back(mem::take(&mut self.partial));
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Next message received from the peer, reliable ones in the order they were sent in.
+    pub fn next_message(&mut self) -> Option<Vec<u8>> {
+        return self.inbox.pop_front();
+    }
+
+    /// Datagrams to send to the peer: queued messages, reliable ones that weren't acknowledged in time and pending acks.
+    pub fn poll(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let resend_timeout = (self.rtt * 2).max(MIN_RESEND_TIMEOUT);
+        let base = self.base();
+        let mut messages = mem::take(&mut self.unreliable);
+        for outgoing in self.reliable.iter_mut().take(RELIABLE_WINDOW as usize) {
+            if outgoing.last_sent.is_none_or(|last_sent| now - last_sent >= resend_timeout) {
+                outgoing.last_sent = Some(now);
+                messages.push(Message::Reliable { id: outgoing.id, last: outgoing.last, payload: outgoing.payload.clone() });
+            }
+        }
+
+        // Pack as many messages into a datagram as fit
+        let mut batches: Vec<Vec<Message>> = vec![];
+        let mut batch_size = 0;
+        for message in messages {
+            let size = MESSAGE_OVERHEAD + message.payload().len();
+            match batches.last_mut() {
+                Some(batch) if batch_size + size <= MAX_DATAGRAM_SIZE => batch.push(message),
+                _ => {
+                    batches.push(vec![message]);
+                    batch_size = DATAGRAM_OVERHEAD;
+                }
+            }
+
+            batch_size += size;
+        }
+
+        if batches.is_empty() && self.ack_pending {
+            batches.push(vec![]);
+        }
+
+        self.ack_pending = false;
+        self.in_flight.retain(|sequence, _| self.local_sequence.wrapping_sub(*sequence) < ACK_WINDOW);
+
+        return batches.into_iter().map(|messages| {
+            let sequence = self.local_sequence;
+            self.local_sequence = self.local_sequence.wrapping_add(1);
+
+            let reliable: Vec<u16> = messages.iter().filter_map(|message| match message {
+                Message::Reliable { id, .. } => Some(*id),
+                _ => None,
+            }).collect();
+
+            if !reliable.is_empty() {
+                self.in_flight.insert(sequence, InFlight { sent: now, messages: reliable });
+            }
+
+            let datagram = Datagram {
+                session  : self.session,
+                base,
+                sequence,
+                ack      : self.remote_sequence,
+                ack_bits : self.received,
+                expected : self.next_expected,
+                messages,
+            };
+
+            bincode::serialize(&datagram).unwrap()
+        }).collect();
+    }
+
+    /// Smoothed round trip time, measured from acknowledgements of reliable messages.
+    pub fn rtt(&self) -> Duration {
+        return self.rtt;
+    }
+
+    /// When the last datagram from the peer arrived, `None` if nothing did yet.
+    pub fn last_received(&self) -> Option<Instant> {
+        return self.last_received;
+    }
+
+    /// Reliable messages still waiting for an acknowledgement.
+    pub fn unacknowledged(&self) -> usize {
+        return self.reliable.len();
+    }
+
+    // Oldest reliable message not acknowledged yet, or the next one if there are none
+    fn base(&self) -> u16 {
+        return self.reliable.front().map_or(self.next_id, |outgoing| outgoing.id);
+    }
+
+    fn reassemble(&mut self, group: u16, index: u16, count: u16, payload: Vec<u8>, now: Instant) -> Result<()> {
+        if index >= count || count as usize > MAX_MESSAGE_SIZE / FRAGMENT_SIZE || payload.len() > FRAGMENT_SIZE {
+            bail!("Invalid fragment {} of {}", index, count);
+        }
+
+        if !self.fragments.contains_key(&group) && self.fragments.len() >= MAX_REASSEMBLIES {
+            bail!("Too many fragmented messages at once, dropped a fragment of group {}", group);
+        }
+
+        let reassembly = self.fragments.entry(group).or_insert_with(|| Reassembly {
+            started   : now,
+            remaining : count as usize,
+            pieces    : vec![None; count as usize],
+        });
+
+        if reassembly.pieces.len() != count as usize {
+            bail!("Fragment count of group {} changed", group);
+        }
+
+        let piece = &mut reassembly.pieces[index as usize];
+        if piece.is_none() {
+            *piece = Some(payload);
+            reassembly.remaining -= 1;
+        }
+
+        if reassembly.remaining == 0 {
+            let reassembly = self.fragments.remove(&group).unwrap();
+            self.inbox.push_back(reassembly.pieces.into_iter().flatten().flatten().collect());
+        }
+
+        return Ok(());
+    }
+
+    fn acknowledge(&mut self, ack: u16, ack_bits: u32, now: Instant) {
+        let mut acked = vec![];
+        for bit in 0 .. ACK_WINDOW {
+            if bit == 0 || ack_bits & 1 << (bit - 1) != 0 {
+                if let Some(in_flight) = self.in_flight.remove(&ack.wrapping_sub(bit)) {
+                    // Same smoothing as TCP
+                    let sample = now - in_flight.sent;
+                    self.rtt = (self.rtt * 7 + sample) / 8;
+                    acked.extend(in_flight.messages);
+                }
+            }
+        }
+
+        if !acked.is_empty() {
+            self.reliable.retain(|outgoing| !acked.contains(&outgoing.id));
+        }
+    }
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// A UDP socket with a `Connection` for every peer it talks to.
+pub struct Transport {
+    socket      : UdpSocket,
+    connections : HashMap<SocketAddr, Connection>,
+    buffer      : Vec<u8>,
+}
+
+impl Transport {
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+
+        return Ok(Self {
+            socket,
+            connections : HashMap::new(),
+            buffer      : vec![0; 64 * 1024],
+        });
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        return Ok(self.socket.local_addr()?);
+    }
+
+    /// Queues a message to the peer, sent out on the next `flush`.
+    pub fn send(&mut self, address: SocketAddr, channel: Channel, payload: Vec<u8>) {
+        self.connections.entry(address).or_default().send(channel, payload);
+    }
+
+    /// Next message received from any peer, or `None` once everything that arrived so far was handled.
+    pub fn receive(&mut self, now: Instant) -> Result<Option<(SocketAddr, Vec<u8>)>> {
+        loop {
+            for (address, connection) in &mut self.connections {
+                if let Some(message) = connection.next_message() {
+                    return Ok(Some((*address, message)));
+                }
+            }
+
+            let (read, address) = match self.socket.recv_from(&mut self.buffer) {
+                Ok(received) => received,
+                Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+
+                // Windows reports datagrams sent earlier that the peer didn't accept
+                Err(error) if matches!(error.kind(), ErrorKind::ConnectionReset | ErrorKind::Interrupted) => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            // Peers only get a connection once they sent something valid, anything else would be kept around for nothing
+            let bytes = &self.buffer[..read];
+            let result = match self.connections.get_mut(&address) {
+                Some(connection) => connection.receive(bytes, now),
+                None => {
+                    let mut connection = Connection::new();
+                    connection.receive(bytes, now).map(|_| { self.connections.insert(address, connection); })
+                }
+            };
+
+            if let Err(error) = result {
+                error!("Invalid datagram from {}: {}", address, error);
+            }
+        }
+    }
+
+    /// Sends out everything the connections have queued.
+    pub fn flush(&mut self, now: Instant) {
+        for (address, connection) in &mut self.connections {
+            for datagram in connection.poll(now) {
+                // Lost datagrams are no different from those lost on the way, reliable messages get resent
+                if let Err(error) = self.socket.send_to(&datagram, address) {
+                    error!("Failed to send a datagram to {}: {}", address, error);
+                }
+            }
+        }
+    }
+
+    /// Blocks until a datagram arrives or the timeout runs out.
+    pub fn wait(&self, timeout: Duration) -> Result<()> {
+        self.socket.set_nonblocking(false)?;
+        self.socket.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+
+        // Errors show up again on the next `receive`
+        let _ = self.socket.peek_from(&mut [0; 1]);
+
+        self.socket.set_nonblocking(true)?;
+        return Ok(());
+    }
+
+    /// Forgets about the peer, dropping anything not yet sent to it.
+    pub fn disconnect(&mut self, address: SocketAddr) {
+        self.connections.remove(&address);
+    }
+
+    /// Forgets about peers that didn't send anything for the timeout, returning their addresses.
+    pub fn disconnect_idle(&mut self, now: Instant, timeout: Duration) -> Vec<SocketAddr> {
+        let idle: Vec<SocketAddr> = self.connections.iter()
+            .filter(|(_, connection)| connection.last_received.is_some_and(|last_received| now - last_received >= timeout))
+            .map(|(address, _)| *address)
+            .collect();
+
+        for address in &idle {
+            self.connections.remove(address);
+        }
+
+        return idle;
+    }
+
+    pub fn connection(&self, address: SocketAddr) -> Option<&Connection> {
+        return self.connections.get(&address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagram(session: u32, base: u16, sequence: u16, messages: Vec<Message>) -> Vec<u8> {
+        return bincode::serialize(&Datagram { session, base, sequence, ack: u16::MAX, ack_bits: 0, expected: 0, messages }).unwrap();
+    }
+
+    // Two sockets connected to each other over loopback
+    fn socket_pair() -> (UdpSocket, UdpSocket) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        a.connect(b.local_addr().unwrap()).unwrap();
+        b.connect(a.local_addr().unwrap()).unwrap();
+        a.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        b.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        return (a, b);
+    }
+
+    // Sends the datagrams over the socket and returns them as they arrive on the other end
+    fn transmit(from: &UdpSocket, to: &UdpSocket, datagrams: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        for datagram in datagrams {
+            from.send(datagram).unwrap();
+        }
+
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE * 2];
+        return datagrams.iter().map(|_| {
+            let read = to.recv(&mut buffer).unwrap();
+            buffer[..read].to_vec()
+        }).collect();
+    }
+
+    fn random(state: &mut u64) -> u64 {
+        *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        return *state >> 33;
+    }
+
+    // Drops about a third of the datagrams and shuffles the rest
+    fn lossy(datagrams: Vec<Vec<u8>>, state: &mut u64) -> Vec<Vec<u8>> {
+        let mut kept: Vec<Vec<u8>> = datagrams.into_iter().filter(|_| !random(state).is_multiple_of(3)).collect();
+        for i in (1 .. kept.len()).rev() {
+            kept.swap(i, random(state) as usize % (i + 1));
+        }
+
+        return kept;
+    }
+
+    fn deliver(from: &mut Connection, to: &mut Connection, now: Instant) {
+        for datagram in from.poll(now) {
+            to.receive(&datagram, now).unwrap();
+        }
+    }
+
+    #[test]
+    fn sequence_greater_wraps_around() {
+        assert!(sequence_greater(1, 0));
+        assert!(!sequence_greater(0, 1));
+        assert!(!sequence_greater(5, 5));
+        assert!(sequence_greater(0, u16::MAX));
+        assert!(sequence_greater(10, 65530));
+        assert!(!sequence_greater(65530, 10));
+        assert!(sequence_greater(0x7fff, 0));
+        assert!(!sequence_greater(0x8000, 0));
+    }
+
+    #[test]
+    fn ack_bits_track_received_datagrams() {
+        let now = Instant::now();
+        let mut connection = Connection::new();
+        for sequence in [65534, 65535, 1] {
+            connection.receive(&datagram(1, 0, sequence, vec![]), now).unwrap();
+        }
+
+        assert_eq!(connection.remote_sequence, 1);
+        assert_eq!(connection.received, 0b110);
+
+        // Late datagrams fill in their bit
+        connection.receive(&datagram(1, 0, 0, vec![]), now).unwrap();
+        assert_eq!(connection.received, 0b111);
+
+        // Duplicates are dropped along with their messages
+        connection.receive(&datagram(1, 0, 65535, vec![Message::Unreliable(vec![1])]), now).unwrap();
+        assert_eq!(connection.next_message(), None);
+
+        // Skipping more than the ack bits cover forgets about everything before
+        connection.receive(&datagram(1, 0, 40, vec![]), now).unwrap();
+        assert_eq!(connection.remote_sequence, 40);
+        assert_eq!(connection.received, 0);
+    }
+
+    #[test]
+    fn acks_across_wrap_around() {
+        let now = Instant::now();
+        let mut sender = Connection::new();
+        let mut receiver = Connection::new();
+        sender.local_sequence = 65530;
+        receiver.remote_sequence = 65529;
+
+        // Every other datagram is lost, one message each
+        for i in 0 .. 10 {
+            sender.send(Channel::Reliable, vec![i]);
+            let datagrams = sender.poll(now);
+            assert_eq!(datagrams.len(), 1);
+            if i % 2 == 0 {
+                receiver.receive(&datagrams[0], now).unwrap();
+            }
+        }
+
+        deliver(&mut receiver, &mut sender, now);
+        let unacknowledged: Vec<u16> = sender.reliable.iter().map(|outgoing| outgoing.id).collect();
+        assert_eq!(unacknowledged, [1, 3, 5, 7, 9]);
+
+        // Resent once the timeout runs out, and then everything arrives in order
+        let later = now + MIN_RESEND_TIMEOUT.max(sender.rtt() * 2);
+        deliver(&mut sender, &mut receiver, later);
+        deliver(&mut receiver, &mut sender, later);
+        assert_eq!(sender.unacknowledged(), 0);
+        for i in 0 .. 10 {
+            assert_eq!(receiver.next_message(), Some(vec![i]));
+        }
+    }
+
+    #[test]
+    fn sessions_have_to_start_from_the_first_message() {
+        let now = Instant::now();
+        let mut connection = Connection::new();
+        assert!(connection.receive(&datagram(1, 3, 0, vec![]), now).is_err());
+        assert_eq!(connection.remote_session, None);
+        assert!(connection.receive(&[1, 2, 3], now).is_err());
+        assert_eq!(connection.last_received(), None);
+
+        connection.receive(&datagram(1, 0, 0, vec![]), now).unwrap();
+        assert_eq!(connection.remote_session, Some(1));
+    }
+
+    #[test]
+    fn restarted_peers_start_over() {
+        let now = Instant::now();
+        let mut client = Connection::new();
+        let mut server = Connection::new();
+        for i in 0 .. 3 {
+            client.send(Channel::Reliable, vec![i]);
+        }
+
+        deliver(&mut client, &mut server, now);
+        deliver(&mut server, &mut client, now);
+        while server.next_message().is_some() {}
+
+        // Ids of the new session start at 0 again, they'd look like duplicates otherwise
+        server.send(Channel::Reliable, vec![10]);
+        let mut restarted = Connection::new();
+        restarted.send(Channel::Reliable, vec![20]);
+        deliver(&mut restarted, &mut server, now);
+        assert_eq!(server.next_message(), Some(vec![20]));
+        assert_eq!(server.unacknowledged(), 0);
+
+        // The old session is gone
+        client.send(Channel::Reliable, vec![3]);
+        for datagram in client.poll(now) {
+            assert!(server.receive(&datagram, now).is_err());
+        }
+    }
+
+    #[test]
+    fn reliable_messages_stay_in_the_window() {
+        let now = Instant::now();
+        let mut connection = Connection::new();
+        let messages = [RELIABLE_WINDOW, u16::MAX, RELIABLE_WINDOW - 1].map(|id| Message::Reliable { id, last: true, payload: vec![] });
+        connection.receive(&datagram(1, 0, 0, messages.into()), now).unwrap();
+        assert_eq!(connection.out_of_order.keys().copied().collect::<Vec<_>>(), [RELIABLE_WINDOW - 1]);
+
+        let mut sender = Connection::new();
+        for _ in 0 .. RELIABLE_WINDOW + 10 {
+            sender.send(Channel::Reliable, vec![]);
+        }
+
+        let sent: usize = sender.poll(now).iter().map(|bytes| bincode::deserialize::<Datagram>(bytes).unwrap().messages.len()).sum();
+        assert_eq!(sent, RELIABLE_WINDOW as usize);
+    }
+
+    #[test]
+    fn reassemblies_are_limited() {
+        let now = Instant::now();
+        let mut connection = Connection::new();
+        let fragments = (0 .. MAX_REASSEMBLIES as u16 + 2).map(|group| Message::Fragment { group, index: 0, count: 2, payload: vec![] }).collect();
+        connection.receive(&datagram(1, 0, 0, fragments), now).unwrap();
+        assert_eq!(connection.fragments.len(), MAX_REASSEMBLIES);
+    }
+
+    #[test]
+    fn invalid_datagrams_dont_create_connections() {
+        let now = Instant::now();
+        let mut server = Transport::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+
+        socket.send_to(&[1, 2, 3], server.local_addr().unwrap()).unwrap();
+        socket.send_to(&datagram(1, 5, 0, vec![]), server.local_addr().unwrap()).unwrap();
+        server.wait(Duration::from_secs(1)).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(server.receive(now).unwrap().is_none());
+        assert!(server.connection(address).is_none());
+
+        socket.send_to(&datagram(1, 0, 0, vec![Message::Unreliable(vec![7])]), server.local_addr().unwrap()).unwrap();
+        server.wait(Duration::from_secs(1)).unwrap();
+        assert_eq!(server.receive(now).unwrap(), Some((address, vec![7])));
+        assert!(server.connection(address).is_some());
+    }
+
+    #[test]
+    fn large_reliable_messages_survive_loss_and_reordering() {
+        let (sender_socket, receiver_socket) = socket_pair();
+        let mut sender = Connection::new();
+        let mut receiver = Connection::new();
+        let mut state = 1;
+
+        let large: Vec<u8> = (0 .. FRAGMENT_SIZE * 20 + 100).map(|_| random(&mut state) as u8).collect();
+        sender.send(Channel::Reliable, large.clone());
+        sender.send(Channel::Reliable, vec![1, 2, 3]);
+
+        let mut now = Instant::now();
+        let mut received = vec![];
+        for _ in 0 .. 100 {
+            let datagrams = lossy(sender.poll(now), &mut state);
+            for datagram in transmit(&sender_socket, &receiver_socket, &datagrams) {
+                receiver.receive(&datagram, now).unwrap();
+            }
+
+            while let Some(message) = receiver.next_message() {
+                received.push(message);
+            }
+
+            // Acks get lost too
+            let acks = lossy(receiver.poll(now), &mut state);
+            for datagram in transmit(&receiver_socket, &sender_socket, &acks) {
+                sender.receive(&datagram, now).unwrap();
+            }
+
+            if sender.unacknowledged() == 0 {
+                break;
+            }
+
+            now += MIN_RESEND_TIMEOUT.max(sender.rtt() * 2);
+        }
+
+        assert_eq!(sender.unacknowledged(), 0);
+        assert_eq!(received, [large, vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn incomplete_unreliable_messages_are_discarded() {
+        let (sender_socket, receiver_socket) = socket_pair();
+        let mut sender = Connection::new();
+        let mut receiver = Connection::new();
+        let now = Instant::now();
+
+        sender.send(Channel::Unreliable, vec![5; FRAGMENT_SIZE * 3]);
+        let mut datagrams = sender.poll(now);
+        assert_eq!(datagrams.len(), 3);
+
+        // The middle piece shows up after the others timed out
+        let late = datagrams.remove(1);
+        for datagram in transmit(&sender_socket, &receiver_socket, &datagrams) {
+            receiver.receive(&datagram, now).unwrap();
+        }
+
+        assert_eq!(receiver.next_message(), None);
+        assert_eq!(receiver.fragments[&0].remaining, 1);
+
+        for datagram in transmit(&sender_socket, &receiver_socket, &[late]) {
+            receiver.receive(&datagram, now + FRAGMENT_TIMEOUT).unwrap();
+        }
+
+        assert_eq!(receiver.next_message(), None);
+        assert_eq!(receiver.fragments[&0].remaining, 2);
+
+        // Later messages aren't held up by it
+        sender.send(Channel::Unreliable, vec![6; FRAGMENT_SIZE * 2]);
+        let datagrams = sender.poll(now + FRAGMENT_TIMEOUT);
+        for datagram in transmit(&sender_socket, &receiver_socket, &datagrams) {
+            receiver.receive(&datagram, now + FRAGMENT_TIMEOUT).unwrap();
+        }
+
+        assert_eq!(receiver.next_message(), Some(vec![6; FRAGMENT_SIZE * 2]));
+        assert_eq!(receiver.next_message(), None);
+    }
+
+    #[test]
+    fn invalid_fragments_are_rejected() {
+        let (sender_socket, receiver_socket) = socket_pair();
+        let mut receiver = Connection::new();
+        let now = Instant::now();
+
+        let fragment = |group, index, count, size| Message::Fragment { group, index, count, payload: vec![0; size] };
+        let too_many = (MAX_MESSAGE_SIZE / FRAGMENT_SIZE) as u16 + 1;
+        let messages = vec![
+            fragment(1, 2, 2, 1),
+            fragment(2, 0, 0, 1),
+            fragment(3, 0, too_many, 1),
+            fragment(4, 0, 2, FRAGMENT_SIZE + 1),
+            fragment(5, 0, 2, 1),
+            fragment(5, 1, 3, 1),
+            Message::Reliable { id: 0, last: true, payload: vec![7] },
+            Message::Unreliable(vec![8]),
+        ];
+
+        let datagrams = [datagram(1, 0, 0, messages)];
+        for datagram in transmit(&sender_socket, &receiver_socket, &datagrams) {
+            receiver.receive(&datagram, now).unwrap();
+        }
+
+        // Messages after the bad fragments still arrive, and only the valid fragment is kept
+        assert_eq!(receiver.next_message(), Some(vec![8]));
+        assert_eq!(receiver.next_message(), Some(vec![7]));
+        assert_eq!(receiver.next_message(), None);
+        assert_eq!(receiver.fragments.keys().copied().collect::<Vec<_>>(), [5]);
+        assert_eq!(receiver.fragments[&5].pieces.len(), 2);
+    }
+}
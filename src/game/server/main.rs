@@ -1,94 +1,87 @@
-mod network_player;
-mod server;
+use std::{env, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+use anyhow::{Result, Context};
+use cgmath::vec3;
+use log::info;
+use voxelgame::{game::{client::world::{block_registry::BlockRegistry, terrain_generator::NoiseTerrainGenerator, chunk::chunk::BlockState}, net::transport::Transport, server::{server::{Server, TICK}, region::RegionStorage}}, resources::ResourceManager, utils};
 
-use std::{net::UdpSocket, collections::HashMap};
-use anyhow::Result;
-use log::{error, debug, info};
-use network_player::NetworkPlayer;
-use server::Server;
-use uuid::Uuid as UUID;
-use voxelgame::{game::{client::world::player::Player, net::proto::{ClientPacket, ServerPacket}}, utils};
+// Ticks are skipped rather than caught up with once the server falls this far behind
+const MAX_TICK_LAG: Duration = Duration::from_secs(1);
 
 fn main() -> Result<()> {
     utils::init_logger();
-    let socket = UdpSocket::bind("127.0.0.1:16000")?;
-
-    let mut server = Server::new();
-    let mut buf = [0; 64 * 1024];
-    loop {
-        let (read, src) = socket.recv_from(&mut buf)?;
-        let bytes = &buf[..read];
-
-        if let Ok(packet) = bincode::deserialize::<ClientPacket>(&bytes) {
-            match &packet {
-                  ClientPacket::PlayerJoin  { .. }
-                | ClientPacket::PlayerLeave { .. } => {
-                    debug!("{:?}", packet);
-                }
-
-                _ => {}
-            }
+    let mut transport = Transport::bind("127.0.0.1:16000")?;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))?;
+
+    // World, stored in the `WORLD` directory. For new worlds set the `SEED` environment variable to get a specific one
+    let storage = RegionStorage::new(env::var("WORLD").unwrap_or("world".into()))?;
+    let seed = match storage.load_seed()? {
+        Some(seed) => seed,
+        None => {
+            let seed = match env::var("SEED") {
+                Ok(seed) => seed.parse().context("SEED must be an unsigned 64-bit integer")?,
+                Err(_) => rand::random(),
+            };
+
+            storage.save_seed(seed)?;
+            seed
+        }
+    };
+
+    let registry = BlockRegistry::load(&ResourceManager::from_env()?)?;
+    let surface = block(&registry, &env::var("SURFACE_BLOCK").unwrap_or("panel".into()))?;
+    let filler = block(&registry, &env::var("FILLER_BLOCK").unwrap_or("test".into()))?;
+    let generator = NoiseTerrainGenerator::new(seed, surface, filler);
+    let mut server = Server::new(seed, registry, Box::new(generator), storage);
+    if let Ok(timeout) = env::var("PLAYER_TIMEOUT") {
+        server.player_timeout = Duration::from_secs(timeout.parse().context("PLAYER_TIMEOUT must be a whole number of seconds")?);
+    }
 
-            match packet {
-                ClientPacket::QueryPlayerList => {
-                    let player_list: HashMap<_, _> = server.players.iter()
-                        .map(|(uuid, net_player)| (uuid.clone(), net_player.player.clone()))
-                        .collect();
-
-                    let bytes = bincode::serialize(&player_list)?;
-                    socket.send_to(&bytes, src)?;
-                }
-
-                ClientPacket::PlayerJoin { name } => {
-                    let uuid = UUID::new_v4();
-                    let token = UUID::new_v4();
-                    let player = Player {
-                        name     : name.clone(),
-                        position : (0.0, 0.0, 0.0).into(),
-                    };
-
-                    server.players.insert(uuid, NetworkPlayer {
-                        token   : token,
-                        address : src,
-                        player  : player.clone(),
-                    });
-
-                    info!("New connection: {}@{}", name, uuid);
-
-                    // Send player the auth token
-                    let bytes = [uuid.as_bytes().clone(), token.as_bytes().clone()].concat();
-                    socket.send_to(&bytes, src)?;
-
-                    // Broadcast to others
-                    let player_join_packet = ServerPacket::PlayerJoin { uuid, player };
-                    server.broadcast(&socket, uuid, &bincode::serialize(&player_join_packet)?);
-                }
-
-                ClientPacket::PlayerLeave { token, uuid } => {
-                    if let Some(net_player) = server.players.get(&uuid) {
-                        if net_player.token == token {
-                            server.players.remove(&uuid);
-                            
-                            // Broadcast to others
-                            let player_leave_packet = ServerPacket::PlayerLeave { uuid };
-                            server.broadcast(&socket, uuid, &bincode::serialize(&player_leave_packet)?);
-                        } else { error!("Incorrect player token"); }
-                    } else { error!("No such player on the server"); }
-
-                }
-
-                ClientPacket::PlayerMove { token, uuid, position } => {
-                    if let Some(net_player) = server.players.get_mut(&uuid) {
-                        if net_player.token == token {
-                            net_player.player.position = position;
-
-                            let player_move_packet = ServerPacket::PlayerMove { uuid, position };
-                            server.broadcast(&socket, uuid, &bincode::serialize(&player_move_packet)?);
-                        } else { error!("Incorrect player token"); }
-                    } else { error!("No such player on the server"); }
-
-                }
+    for x in -2 ..= 2 {
+        for y in -1 ..= 1 {
+            for z in -2 ..= 2 {
+                server.load_chunk(vec3(x, y, z))?;
             }
-        } else { error!("Failed to parse incoming packet"); }
+        }
+    }
+
+    info!("World seed: {}", seed);
+
+    // Packets are taken off the socket as they arrive, but only handled on the next tick
+    let mut next_tick = Instant::now();
+    while running.load(Ordering::SeqCst) {
+        while let Some((address, bytes)) = transport.receive(Instant::now())? {
+            server.receive(address, bytes);
+        }
+
+        let now = Instant::now();
+        if now < next_tick {
+            transport.wait(next_tick - now)?;
+            continue;
+        }
+
+        server.tick(TICK);
+        server.flush(&mut transport);
+        transport.flush(now);
+
+        // Connections that never joined or linger after leaving
+        transport.disconnect_idle(now, server.player_timeout);
+
+        next_tick += TICK;
+        if now.saturating_duration_since(next_tick) > MAX_TICK_LAG {
+            next_tick = now;
+        }
     }
-}
\ No newline at end of file
+
+    info!("Shutting down");
+    server.save()?;
+
+    return Ok(());
+}
+
+// Resource packs can rename or drop blocks, so generation blocks are looked up rather than assumed to exist
+fn block(registry: &BlockRegistry, name: &str) -> Result<BlockState> {
+    return registry.by_name(name).with_context(|| format!("Block {:?} is missing from blocks.ron", name));
+}
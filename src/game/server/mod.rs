@@ -0,0 +1,3 @@
+pub mod server;
+pub mod network_player;
+pub mod region;
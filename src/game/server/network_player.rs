@@ -1,11 +1,16 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, collections::HashSet, time::Duration};
 
+use cgmath::Vector3;
 use uuid::Uuid as UUID;
-use voxelgame::game::client::world::player::Player;
+use crate::game::client::world::player::Player;
 
 pub struct NetworkPlayer {
-    pub token   : UUID,
-    pub address : SocketAddr,
-    pub player  : Player,
-    
+    pub token            : UUID,
+    pub address          : SocketAddr,
+    pub player           : Player,
+    pub last_seen        : Duration, // Server time of the last packet authorized with the player's token
+    pub moved            : bool,     // Since the last tick
+
+    // Chunks the player asked for that weren't sent yet
+    pub requested_chunks : HashSet<Vector3<i32>>,
 }
\ No newline at end of file
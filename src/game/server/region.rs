@@ -0,0 +1,384 @@
+use std::{collections::HashMap, fs::{self, File}, io::{Read, Write, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use anyhow::{Result, bail, Context};
+use cgmath::{Vector3, vec3};
+use serde::{Serialize, Deserialize};
+use crate::game::client::world::chunk::chunk::Chunk;
+
+/// Chunks per region along each axis.
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: &[u8; 4] = b"VXRG";
+pub const FORMAT_VERSION: u32 = 2; // 2: paletted chunk storage
+
+// Magic, format version, then an (offset, length) pair per chunk, all little endian.
+// A length of zero means the chunk was never saved.
+const HEADER_SIZE: u64 = 4 + 4 + (REGION_VOLUME as u64) * 8;
+
+#[derive(Serialize, Deserialize)]
+struct Level {
+    version : u32,
+    seed    : u64,
+}
+
+/// Stores chunks on disk, grouped into region files of `REGION_SIZE`³ chunks.
+/// Every chunk is deflate-compressed separately, a region file starts with a table of where each chunk is.
+/// Saving rewrites the whole region into a temporary file that then replaces the old one, so a crash
+/// never leaves a half written region behind and the space of old chunk versions is reclaimed.
+pub struct RegionStorage {
+    directory: PathBuf,
+}
+
+impl RegionStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).with_context(|| format!("Failed to create world directory {:?}", directory))?;
+
+        return Ok(Self { directory });
+    }
+
+    /// Seed of the stored world, `None` for a fresh world directory.
+    pub fn load_seed(&self) -> Result<Option<u64>> {
+        let path = self.directory.join("level.dat");
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let level: Level = bincode::deserialize(&fs::read(path)?)?;
+        if level.version != FORMAT_VERSION {
+            bail!("Unsupported world format version {}, expected {}", level.version, FORMAT_VERSION);
+        }
+
+        return Ok(Some(level.seed));
+    }
+
+    pub fn save_seed(&self, seed: u64) -> Result<()> {
+        let level = Level { version: FORMAT_VERSION, seed };
+        return Self::write_atomic(&self.directory.join("level.dat"), &bincode::serialize(&level)?);
+    }
+
+    pub fn load_chunk(&self, position: Vector3<i32>) -> Result<Option<Chunk>> {
+        let (region, index) = Self::locate(position);
+        let path = self.region_path(region);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut file = File::open(&path)?;
+        Self::check_header(&mut file)?;
+
+        let (offset, length) = Self::read_entry(&mut file, index)?;
+        if length == 0 {
+            return Ok(None);
+        }
+
+        if offset as u64 + length as u64 > file.metadata()?.len() {
+            bail!("Chunk {:?} lies outside of {:?}", position, path);
+        }
+
+        let mut compressed = vec![0; length as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut compressed)?;
+
+        let chunk = Chunk::decompress(&compressed).with_context(|| format!("Corrupted chunk {:?} in {:?}", position, path))?;
+
+        return Ok(Some(chunk));
+    }
+
+    pub fn save_chunk(&self, position: Vector3<i32>, chunk: &Chunk) -> Result<()> {
+        return self.save_chunks(&[(position, chunk)]);
+    }
+
+    /// Saves chunks together, writing each of their regions once.
+    /// Either all chunks of a region are saved or none of them, regions saved before a failure stay saved.
+    pub fn save_chunks(&self, chunks: &[(Vector3<i32>, &Chunk)]) -> Result<()> {
+        let mut regions: HashMap<Vector3<i32>, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        for (position, chunk) in chunks {
+            let (region, index) = Self::locate(*position);
+            regions.entry(region).or_default().push((index, chunk.compress()?));
+        }
+
+        for (region, changed) in regions {
+            let path = self.region_path(region);
+            self.save_region(&path, changed).with_context(|| format!("Failed to save {:?}", path))?;
+        }
+
+        return Ok(());
+    }
+
+    /// Region a chunk is stored in.
+    pub fn region(position: Vector3<i32>) -> Vector3<i32> {
+        return Self::locate(position).0;
+    }
+
+    fn save_region(&self, path: &Path, changed: Vec<(usize, Vec<u8>)>) -> Result<()> {
+        // Chunks that stay the same are copied over from the old file
+        let mut chunks: Vec<Option<Vec<u8>>> = vec![None; REGION_VOLUME];
+        if path.exists() {
+            let mut file = File::open(path)?;
+            Self::check_header(&mut file)?;
+
+            let file_length = file.metadata()?.len();
+            for (index, slot) in chunks.iter_mut().enumerate() {
+                let (offset, length) = Self::read_entry(&mut file, index)?;
+                if length == 0 || changed.iter().any(|(changed, _)| *changed == index) {
+                    continue;
+                }
+
+                if offset as u64 + length as u64 > file_length {
+                    bail!("Corrupted region table entry {}", index);
+                }
+
+                let mut compressed = vec![0; length as usize];
+                file.seek(SeekFrom::Start(offset as u64))?;
+                file.read_exact(&mut compressed)?;
+                *slot = Some(compressed);
+            }
+        }
+
+        for (index, compressed) in changed {
+            chunks[index] = Some(compressed);
+        }
+
+        // Chunks are laid out back to back after the table
+        let mut table = Vec::with_capacity(REGION_VOLUME * 8);
+        let mut data: Vec<u8> = vec![];
+        for chunk in &chunks {
+            let (offset, length) = match chunk {
+                Some(compressed) => (HEADER_SIZE + data.len() as u64, compressed.len()),
+                None => (0, 0),
+            };
+
+            if offset > u32::MAX as u64 {
+                bail!("Region is too large");
+            }
+
+            table.extend((offset as u32).to_le_bytes());
+            table.extend((length as u32).to_le_bytes());
+            data.extend(chunk.iter().flatten());
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE as usize + data.len());
+        bytes.extend(MAGIC);
+        bytes.extend(FORMAT_VERSION.to_le_bytes());
+        bytes.extend(table);
+        bytes.extend(data);
+
+        return Self::write_atomic(path, &bytes);
+    }
+
+    // Writes a temporary file next to the destination and moves it over, readers see either the old or the new file
+    fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        let mut file = File::create(&temporary)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)?;
+
+        return Ok(());
+    }
+
+    // Region position and the chunk's index inside of its table
+    fn locate(position: Vector3<i32>) -> (Vector3<i32>, usize) {
+        let region = vec3(position.x.div_euclid(REGION_SIZE), position.y.div_euclid(REGION_SIZE), position.z.div_euclid(REGION_SIZE));
+        let local = vec3(position.x.rem_euclid(REGION_SIZE), position.y.rem_euclid(REGION_SIZE), position.z.rem_euclid(REGION_SIZE));
+        let index = (local.z * REGION_SIZE * REGION_SIZE + local.y * REGION_SIZE + local.x) as usize;
+
+        return (region, index);
+    }
+
+    fn region_path(&self, region: Vector3<i32>) -> PathBuf {
+        return self.directory.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z));
+    }
+
+    fn check_header(file: &mut File) -> Result<()> {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+
+        if &header[..4] != MAGIC {
+            bail!("Not a region file");
+        }
+
+        let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            bail!("Unsupported region format version {}, expected {}", version, FORMAT_VERSION);
+        }
+
+        return Ok(());
+    }
+
+    fn read_entry(file: &mut File, index: usize) -> Result<(u32, u32)> {
+        let mut entry = [0; 8];
+        file.seek(SeekFrom::Start(Self::entry_offset(index)))?;
+        file.read_exact(&mut entry)?;
+
+        let offset = u32::from_le_bytes(entry[..4].try_into().unwrap());
+        let length = u32::from_le_bytes(entry[4..].try_into().unwrap());
+        if length != 0 && (offset as u64) < HEADER_SIZE {
+            bail!("Corrupted region table entry {}", index);
+        }
+
+        return Ok((offset, length));
+    }
+
+    const fn entry_offset(index: usize) -> u64 {
+        return 8 + index as u64 * 8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, sync::atomic::{AtomicUsize, Ordering}};
+
+    use crate::game::client::world::chunk::chunk::{BlockState, CHUNK_SIZE};
+
+    use super::*;
+
+    // Fresh directory per test, tests run in parallel
+    fn storage() -> (RegionStorage, PathBuf) {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let directory = env::temp_dir().join(format!("voxelgame-region-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&directory);
+
+        return (RegionStorage::new(&directory).unwrap(), directory);
+    }
+
+    // Chunk whose contents depend on `seed`, with enough different blocks to compress to different sizes
+    fn chunk(seed: u16, variety: u16) -> Chunk {
+        let mut chunk = Chunk::new();
+        for x in 0 .. CHUNK_SIZE {
+            for y in 0 .. CHUNK_SIZE {
+                for z in 0 .. CHUNK_SIZE {
+                    let value = (x * 7 + y * 13 + z * 31) as u16 ^ seed;
+                    chunk.set(x, y, z, BlockState(value % variety));
+                }
+            }
+        }
+
+        return chunk;
+    }
+
+    fn same(a: &Chunk, b: &Chunk) -> bool {
+        return bincode::serialize(a).unwrap() == bincode::serialize(b).unwrap();
+    }
+
+    fn region_file(directory: &Path, position: Vector3<i32>) -> PathBuf {
+        let region = RegionStorage::region(position);
+        return directory.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z));
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let (storage, directory) = storage();
+        let positions = [vec3(0, 0, 0), vec3(7, 7, 7), vec3(8, 0, 0), vec3(-1, -1, -1), vec3(-9, 3, 100)];
+        for (i, position) in positions.iter().enumerate() {
+            storage.save_chunk(*position, &chunk(i as u16, 5)).unwrap();
+        }
+
+        for (i, position) in positions.iter().enumerate() {
+            assert!(same(&storage.load_chunk(*position).unwrap().unwrap(), &chunk(i as u16, 5)));
+        }
+
+        assert!(storage.load_chunk(vec3(1, 0, 0)).unwrap().is_none());
+        assert!(storage.load_chunk(vec3(100, 100, 100)).unwrap().is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rewriting_reuses_space() {
+        let (storage, directory) = storage();
+        let position = vec3(1, 2, 3);
+        storage.save_chunk(vec3(0, 0, 0), &chunk(1, 3)).unwrap();
+
+        // Alternate between chunks of different sizes, the file only ever holds the latest version
+        for round in 0 .. 10 {
+            let variety = if round % 2 == 0 { 200 } else { 2 };
+            storage.save_chunk(position, &chunk(round, variety)).unwrap();
+            assert!(same(&storage.load_chunk(position).unwrap().unwrap(), &chunk(round, variety)));
+
+            let expected = HEADER_SIZE as usize + chunk(1, 3).compress().unwrap().len() + chunk(round, variety).compress().unwrap().len();
+            assert_eq!(fs::metadata(region_file(&directory, position)).unwrap().len() as usize, expected);
+        }
+
+        assert!(same(&storage.load_chunk(vec3(0, 0, 0)).unwrap().unwrap(), &chunk(1, 3)));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn saving_several_chunks() {
+        let (storage, directory) = storage();
+        let chunks: Vec<_> = (0 .. 20).map(|i| (vec3(i - 10, i % 3, 0), chunk(i as u16, 4))).collect();
+        let references: Vec<_> = chunks.iter().map(|(position, chunk)| (*position, chunk)).collect();
+        storage.save_chunks(&references).unwrap();
+
+        for (position, chunk) in &chunks {
+            assert!(same(&storage.load_chunk(*position).unwrap().unwrap(), chunk));
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn seed_round_trip() {
+        let (storage, directory) = storage();
+        assert_eq!(storage.load_seed().unwrap(), None);
+
+        storage.save_seed(0xDEAD_BEEF).unwrap();
+        assert_eq!(storage.load_seed().unwrap(), Some(0xDEAD_BEEF));
+
+        let level = Level { version: FORMAT_VERSION + 1, seed: 1 };
+        fs::write(directory.join("level.dat"), bincode::serialize(&level).unwrap()).unwrap();
+        assert!(storage.load_seed().is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let (storage, directory) = storage();
+        let position = vec3(0, 0, 0);
+        storage.save_chunk(position, &chunk(0, 2)).unwrap();
+        let path = region_file(&directory, position);
+        let original = fs::read(&path).unwrap();
+
+        let mut version = original.clone();
+        version[4 .. 8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&path, version).unwrap();
+        assert!(storage.load_chunk(position).is_err());
+        assert!(storage.save_chunk(vec3(1, 0, 0), &chunk(1, 2)).is_err());
+
+        let mut magic = original.clone();
+        magic[0] = b'X';
+        fs::write(&path, magic).unwrap();
+        assert!(storage.load_chunk(position).is_err());
+
+        // Table entry pointing past the end of the file
+        let mut entry = original.clone();
+        entry[8 .. 12].copy_from_slice(&(original.len() as u32).to_le_bytes());
+        fs::write(&path, entry).unwrap();
+        assert!(storage.load_chunk(position).is_err());
+        assert!(storage.save_chunk(vec3(1, 0, 0), &chunk(1, 2)).is_err());
+
+        // Table entry pointing into the header
+        let mut entry = original.clone();
+        entry[8 .. 12].copy_from_slice(&4u32.to_le_bytes());
+        fs::write(&path, entry).unwrap();
+        assert!(storage.load_chunk(position).is_err());
+
+        // Truncated table
+        fs::write(&path, &original[.. 100]).unwrap();
+        assert!(storage.load_chunk(position).is_err());
+
+        // Failed saves leave the file alone
+        fs::write(&path, &original).unwrap();
+        assert!(same(&storage.load_chunk(position).unwrap().unwrap(), &chunk(0, 2)));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
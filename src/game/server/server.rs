@@ -1,29 +1,514 @@
-use std::{collections::HashMap, net::UdpSocket};
+use std::{collections::{HashMap, HashSet}, net::SocketAddr, time::Duration, mem};
 
+use anyhow::{Result, bail};
+use cgmath::{Vector3, InnerSpace, vec3};
+use log::{info, debug, error};
 use uuid::Uuid as UUID;
 
-use crate::network_player::NetworkPlayer;
+use crate::game::{client::world::{world::World, terrain_generator::TerrainGenerator, chunk::chunk::BlockState, block_registry::BlockRegistry, player::Player}, net::{proto::{ClientPacket, ServerPacket, WorldInfo, JoinRejection, PROTOCOL_VERSION, KEEP_ALIVE_INTERVAL}, transport::{Transport, Channel}}};
 
+use super::{network_player::NetworkPlayer, region::RegionStorage};
+
+/// Simulation rate of the server.
+pub const TICKS_PER_SECOND: u32 = 20;
+
+/// Time between ticks, the `dt` the server normally runs at.
+pub const TICK: Duration = Duration::from_millis(1000 / TICKS_PER_SECOND as u64);
+
+/// How often modified chunks are written to disk.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Chunks sent to each player per tick, see `stream_chunks`.
+pub const CHUNKS_PER_TICK: usize = 4;
+
+/// Players turned away once this many are on the server.
+pub const MAX_PLAYERS: usize = 32;
+
+/// Players who don't send anything for this long are disconnected, unless `Server::player_timeout` is changed.
+pub const DEFAULT_PLAYER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests for chunks further than this many chunks away from the player on any axis are ignored.
+pub const MAX_CHUNK_DISTANCE: i32 = 16;
+
+/// The game server without any networking: packets go into `receive`, get handled on the next `tick`,
+/// and whatever has to be sent back is left in `outbox`. Being driven by `tick` alone, it behaves the same
+/// for the same packets and `dt`s no matter where they come from.
 pub struct Server {
-    pub players : HashMap<UUID, NetworkPlayer>,
+    pub players        : HashMap<UUID, NetworkPlayer>,
+    pub seed           : u64,
+    pub spawn          : Vector3<f32>,
+    pub world          : World,
+    pub registry       : BlockRegistry,
+    pub generator      : Box<dyn TerrainGenerator>,
+    pub storage        : RegionStorage,
+
+    pub player_timeout : Duration,
+
+    /// Sum of every `dt` ticked so far.
+    pub time           : Duration,
+
+    /// Packets received since the last tick.
+    pub inbox          : Vec<(SocketAddr, Vec<u8>)>,
+
+    /// Packets to send and peers to disconnect, see `flush`.
+    pub outbox         : Vec<(SocketAddr, Channel, Vec<u8>)>,
+    pub disconnected   : Vec<SocketAddr>,
+
+    // Chunks modified since they were last written to disk
+    pub unsaved        : HashSet<Vector3<i32>>,
+
+    last_keep_alive    : Duration,
+    last_save          : Duration,
 }
 
 impl Server {
-    pub fn new() -> Self {
+    pub fn new(seed: u64, registry: BlockRegistry, generator: Box<dyn TerrainGenerator>, storage: RegionStorage) -> Self {
         let players = HashMap::<UUID, NetworkPlayer>::new();
+        let spawn = vec3(0.5, generator.height(0, 0) as f32 + 2.0, 0.5);
         
         return Self {
-            players
+            players,
+            seed,
+            spawn,
+            world: World::new(),
+            registry,
+            generator,
+            storage,
+            player_timeout: DEFAULT_PLAYER_TIMEOUT,
+            time: Duration::ZERO,
+            inbox: vec![],
+            outbox: vec![],
+            disconnected: vec![],
+            unsaved: HashSet::new(),
+            last_keep_alive: Duration::ZERO,
+            last_save: Duration::ZERO,
+        };
+    }
+
+    /// Queues a packet, it's handled on the next `tick`.
+    pub fn receive(&mut self, address: SocketAddr, bytes: Vec<u8>) {
+        self.inbox.push((address, bytes));
+    }
+
+    /// Advances the server by `dt`: handles the received packets, streams chunks,
+    /// sends out movement and keep-alives, drops silent players and saves when it's time to.
+    /// Errors are logged rather than returned, one bad packet, chunk or save mustn't stop the server.
+    pub fn tick(&mut self, dt: Duration) {
+        self.time += dt;
+
+        for (address, bytes) in mem::take(&mut self.inbox) {
+            if let Err(error) = self.handle(address, &bytes) {
+                error!("Failed to handle a packet from {}: {:#}", address, error);
+            }
+        }
+
+        if let Err(error) = self.evict_inactive() {
+            error!("Failed to evict inactive players: {:#}", error);
+        }
+
+        self.stream_chunks();
+        if let Err(error) = self.broadcast_movement() {
+            error!("Failed to send player movement: {:#}", error);
+        }
+
+        if self.time - self.last_keep_alive >= KEEP_ALIVE_INTERVAL {
+            self.last_keep_alive = self.time;
+            if let Err(error) = self.broadcast_all(Channel::Unreliable, &ServerPacket::KeepAlive) {
+                error!("Failed to send keep-alives: {:#}", error);
+            }
+        }
+
+        // Chunks that failed stay unsaved and are tried again on the next save
+        if self.time - self.last_save >= SAVE_INTERVAL {
+            self.last_save = self.time;
+            if let Err(error) = self.save() {
+                error!("{:#}", error);
+            }
+        }
+    }
+
+    /// Hands everything in the outbox over to the transport.
+    pub fn flush(&mut self, transport: &mut Transport) {
+        for (address, channel, bytes) in self.outbox.drain(..) {
+            transport.send(address, channel, bytes);
+        }
+
+        for address in self.disconnected.drain(..) {
+            transport.disconnect(address);
+        }
+    }
+
+    /// Reads the chunk from disk, or generates it if it was never saved. Does nothing if it's already loaded.
+    pub fn load_chunk(&mut self, position: Vector3<i32>) -> Result<()> {
+        if self.world.chunk(position).is_none() {
+            let chunk = match self.storage.load_chunk(position)? {
+                Some(chunk) => chunk,
+                None => {
+                    // Saved right away, so later changes to the generator don't affect existing terrain
+                    self.unsaved.insert(position);
+                    self.generator.generate(position)
+                }
+            };
+
+            self.world.insert(position, chunk);
+        }
+
+        return Ok(());
+    }
+
+    /// Returns `false` if the chunk containing the block isn't loaded.
+    pub fn set_block(&mut self, position: Vector3<i32>, block_state: BlockState) -> bool {
+        if self.world.set_block(position, block_state) {
+            self.unsaved.insert(World::split(position).0);
+            return true;
+        }
+
+        return false;
+    }
+
+    pub fn world_info(&self) -> WorldInfo {
+        return WorldInfo {
+            seed  : self.seed,
+            spawn : self.spawn,
+        };
+    }
+
+    /// Handles a single packet from the address.
+    pub fn handle(&mut self, src: SocketAddr, bytes: &[u8]) -> Result<()> {
+        if let Ok(packet) = bincode::deserialize::<ClientPacket>(bytes) {
+            match &packet {
+                  ClientPacket::Connect     { .. }
+                | ClientPacket::PlayerLeave { .. } => {
+                    debug!("{:?}", packet);
+                }
+
+                _ => {}
+            }
+
+            match packet {
+                ClientPacket::Connect { protocol_version, name } => {
+                    match self.join(src, protocol_version, name.clone()) {
+                        Ok((uuid, token)) => {
+                            info!("New connection: {}@{}", name, uuid);
+
+                            let join_accepted_packet = ServerPacket::JoinAccepted {
+                                uuid,
+                                token,
+                                world   : self.world_info(),
+                                players : self.player_list(uuid),
+                            };
+
+                            self.send(src, Channel::Reliable, &join_accepted_packet)?;
+
+                            // Broadcast to others
+                            let player = self.players[&uuid].player.clone();
+                            let player_join_packet = ServerPacket::PlayerJoin { uuid, player };
+                            self.broadcast(Channel::Reliable, uuid, &player_join_packet)?;
+                        }
+
+                        Err(reason) => {
+                            info!("Rejected connection of {}@{}: {}", name, src, reason);
+                            self.send(src, Channel::Reliable, &ServerPacket::JoinRejected { reason })?;
+                        }
+                    }
+                }
+
+                ClientPacket::PlayerLeave { token, uuid } => {
+                    if let Some(net_player) = self.players.get(&uuid) {
+                        if net_player.token == token {
+                            self.players.remove(&uuid);
+                            self.disconnected.push(src);
+
+                            // Broadcast to others
+                            let player_leave_packet = ServerPacket::PlayerLeave { uuid };
+                            self.broadcast(Channel::Reliable, uuid, &player_leave_packet)?;
+                        } else { error!("Incorrect player token"); }
+                    } else { error!("No such player on the server"); }
+
+                }
+
+                ClientPacket::PlayerMove { token, uuid, position } => {
+                    match self.authorize(uuid, token) {
+                        // Sent out with everyone else's movement at the end of the tick
+                        Ok(net_player) => {
+                            net_player.player.position = position;
+                            net_player.moved = true;
+                        }
+
+                        Err(error) => error!("Rejected player movement: {}", error),
+                    }
+                }
+
+                ClientPacket::KeepAlive { token, uuid } => {
+                    if let Err(error) = self.authorize(uuid, token) {
+                        error!("Rejected keep-alive: {}", error);
+                    }
+                }
+
+                ClientPacket::BreakBlock { token, uuid, position } => {
+                    match self.edit_block(uuid, token, position, BlockState::AIR) {
+                        Ok(()) => {
+                            let block_change_packet = ServerPacket::BlockChange { position, block: BlockState::AIR };
+                            self.broadcast_all(Channel::Reliable, &block_change_packet)?;
+                        }
+
+                        Err(error) => error!("Rejected block break: {}", error),
+                    }
+                }
+
+                ClientPacket::PlaceBlock { token, uuid, position, block } => {
+                    match self.edit_block(uuid, token, position, block) {
+                        Ok(()) => {
+                            let block_change_packet = ServerPacket::BlockChange { position, block };
+                            self.broadcast_all(Channel::Reliable, &block_change_packet)?;
+                        }
+
+                        Err(error) => error!("Rejected block placement: {}", error),
+                    }
+                }
+
+                ClientPacket::RequestChunks { token, uuid, positions } => {
+                    if let Err(error) = self.request_chunks(uuid, token, positions) {
+                        error!("Rejected chunk request: {}", error);
+                    }
+                }
+            }
+        } else { error!("Failed to parse incoming packet"); }
+
+        return Ok(());
+    }
+
+    /// Adds a player connecting from the address, returning their UUID and token.
+    pub fn join(&mut self, address: SocketAddr, protocol_version: u32, name: String) -> Result<(UUID, UUID), JoinRejection> {
+        if protocol_version != PROTOCOL_VERSION {
+            return Err(JoinRejection::VersionMismatch { server: PROTOCOL_VERSION, client: protocol_version });
+        }
+
+        if self.players.values().any(|net_player| net_player.player.name == name) {
+            return Err(JoinRejection::NameTaken);
+        }
+
+        if self.players.len() >= MAX_PLAYERS {
+            return Err(JoinRejection::ServerFull);
+        }
+
+        let uuid = UUID::new_v4();
+        let token = UUID::new_v4();
+        self.players.insert(uuid, NetworkPlayer {
+            token,
+            address,
+            player           : Player { name, position: self.spawn },
+            last_seen        : self.time,
+            moved            : false,
+            requested_chunks : HashSet::new(),
+        });
+
+        return Ok((uuid, token));
+    }
+
+    /// Every player but the one with the UUID.
+    pub fn player_list(&self, except: UUID) -> HashMap<UUID, Player> {
+        return self.players.iter()
+            .filter(|(uuid, _)| **uuid != except)
+            .map(|(uuid, net_player)| (*uuid, net_player.player.clone()))
+            .collect();
+    }
+
+    /// Looks up a player, making sure the packet really came from them.
+    pub fn authorize(&mut self, uuid: UUID, token: UUID) -> Result<&mut NetworkPlayer> {
+        let time = self.time;
+        return match self.players.get_mut(&uuid) {
+            Some(net_player) if net_player.token == token => {
+                net_player.last_seen = time;
+                Ok(net_player)
+            }
+
+            Some(_) => bail!("Incorrect player token"),
+            None => bail!("No such player on the server"),
         };
     }
 
-    pub fn broadcast(&self, socket: &UdpSocket, uuid: UUID, bytes: &[u8]) {
+    /// Applies a block edit requested by a player, `block_state` being air for breaking.
+    /// Fails without changing anything if the player isn't allowed to make it.
+    pub fn edit_block(&mut self, uuid: UUID, token: UUID, position: Vector3<i32>, block_state: BlockState) -> Result<()> {
+        let player = &self.authorize(uuid, token)?.player;
+
+        // Measured to the block's center, with some slack for the player moving while the packet was underway
+        let center = position.cast::<f32>().unwrap() + vec3(0.5, 0.5, 0.5);
+        if (center - player.position).magnitude() > Player::REACH + 1.0 {
+            bail!("Block {:?} is out of {}'s reach", position, player.name);
+        }
+
+        let current = match self.world.get_block(position) {
+            Some(current) => current,
+            None => bail!("Block {:?} isn't loaded", position),
+        };
+
+        if block_state == BlockState::AIR {
+            if current == BlockState::AIR {
+                bail!("Nothing to break at {:?}", position);
+            }
+
+            if self.registry.get(current).is_some_and(|block| block.hardness < 0.0) {
+                bail!("Block {:?} can't be broken", position);
+            }
+        } else {
+            if self.registry.get(block_state).is_none() {
+                bail!("Unknown block {:?}", block_state);
+            }
+
+            if current != BlockState::AIR {
+                bail!("Block {:?} is already taken", position);
+            }
+        }
+
+        self.set_block(position, block_state);
+        return Ok(());
+    }
+
+    /// Queues chunks to be sent to the player, see `stream_chunks`.
+    pub fn request_chunks(&mut self, uuid: UUID, token: UUID, positions: Vec<Vector3<i32>>) -> Result<()> {
+        let net_player = self.authorize(uuid, token)?;
+        let center = World::chunk_at(net_player.player.position);
+        for position in positions {
+            let offset = position - center;
+            if offset.x.abs().max(offset.y.abs()).max(offset.z.abs()) <= MAX_CHUNK_DISTANCE {
+                net_player.requested_chunks.insert(position);
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Sends every player up to `CHUNKS_PER_TICK` of the requested chunks nearest to them,
+    /// loading or generating them as needed. Chunks that fail to load are skipped, the player can ask for them again.
+    pub fn stream_chunks(&mut self) {
+        let uuids: Vec<UUID> = self.players.keys().copied().collect();
+        for uuid in uuids {
+            let net_player = self.players.get_mut(&uuid).unwrap();
+            let center = World::chunk_at(net_player.player.position);
+            let mut requested: Vec<Vector3<i32>> = net_player.requested_chunks.iter().copied().collect();
+            requested.sort_by_key(|position| (position - center).magnitude2());
+            requested.truncate(CHUNKS_PER_TICK);
+
+            for position in &requested {
+                net_player.requested_chunks.remove(position);
+            }
+
+            let address = net_player.address;
+            for position in requested {
+                if let Err(error) = self.send_chunk(address, position) {
+                    error!("Failed to send chunk {:?}: {:#}", position, error);
+                }
+            }
+        }
+    }
+
+    pub fn send_chunk(&mut self, address: SocketAddr, position: Vector3<i32>) -> Result<()> {
+        self.load_chunk(position)?;
+        let data = self.world.chunk(position).unwrap().compress()?;
+        return self.send(address, Channel::Reliable, &ServerPacket::ChunkData { position, data });
+    }
+
+    /// Removes players that weren't heard from for `player_timeout` and lets everyone else know.
+    pub fn evict_inactive(&mut self) -> Result<()> {
+        let inactive: Vec<UUID> = self.players.iter()
+            .filter(|(_, net_player)| self.time.saturating_sub(net_player.last_seen) >= self.player_timeout)
+            .map(|(uuid, _)| *uuid)
+            .collect();
+
+        for uuid in inactive {
+            let net_player = self.players.remove(&uuid).unwrap();
+            info!("{}@{} timed out", net_player.player.name, uuid);
+
+            self.disconnected.push(net_player.address);
+            self.broadcast_all(Channel::Reliable, &ServerPacket::PlayerLeave { uuid })?;
+        }
+
+        return Ok(());
+    }
+
+    /// Sends out the positions of players that moved since the last tick, once per tick however often they did.
+    pub fn broadcast_movement(&mut self) -> Result<()> {
+        let moved: Vec<(UUID, Vector3<f32>)> = self.players.iter_mut()
+            .filter(|(_, net_player)| net_player.moved)
+            .map(|(uuid, net_player)| {
+                net_player.moved = false;
+                (*uuid, net_player.player.position)
+            })
+            .collect();
+
+        for (uuid, position) in moved {
+            self.broadcast(Channel::Unreliable, uuid, &ServerPacket::PlayerMove { uuid, position })?;
+        }
+
+        return Ok(());
+    }
+
+    /// Writes every modified chunk to disk.
+    /// Chunks that fail to save stay marked as modified, so the next save tries them again.
+    pub fn save(&mut self) -> Result<()> {
+        // The server doesn't mesh anything, so the world's remesh tracking can be dropped
+        self.world.dirty.clear();
+
+        if !self.unsaved.is_empty() {
+            info!("Saving {} chunk(s)", self.unsaved.len());
+        }
+
+        let mut regions: HashMap<Vector3<i32>, Vec<Vector3<i32>>> = HashMap::new();
+        for position in &self.unsaved {
+            regions.entry(RegionStorage::region(*position)).or_default().push(*position);
+        }
+
+        let mut failed = 0;
+        for positions in regions.into_values() {
+            let chunks: Vec<_> = positions.iter().filter_map(|position| self.world.chunk(*position).map(|chunk| (*position, chunk))).collect();
+            match self.storage.save_chunks(&chunks) {
+                Ok(()) => {
+                    for position in positions {
+                        self.unsaved.remove(&position);
+                    }
+                }
+
+                Err(error) => {
+                    error!("{:#}", error);
+                    failed += positions.len();
+                }
+            }
+        }
+
+        if failed > 0 {
+            bail!("Failed to save {} chunk(s)", failed);
+        }
+
+        return Ok(());
+    }
+
+    pub fn send(&mut self, address: SocketAddr, channel: Channel, packet: &ServerPacket) -> Result<()> {
+        self.outbox.push((address, channel, bincode::serialize(packet)?));
+        return Ok(());
+    }
+
+    pub fn broadcast_all(&mut self, channel: Channel, packet: &ServerPacket) -> Result<()> {
+        let bytes = bincode::serialize(packet)?;
+        for player in self.players.values() {
+            self.outbox.push((player.address, channel, bytes.clone()));
+        }
+
+        return Ok(());
+    }
+
+    /// Sends the packet to everyone but the player with the UUID.
+    pub fn broadcast(&mut self, channel: Channel, uuid: UUID, packet: &ServerPacket) -> Result<()> {
+        let bytes = bincode::serialize(packet)?;
         for player in &self.players {
             if *player.0 != uuid {
                 let address = player.1.address;
-                socket.send_to(bytes, address).unwrap();
+                self.outbox.push((address, channel, bytes.clone()));
             }
             
         }
+
+        return Ok(());
     }
 }
\ No newline at end of file
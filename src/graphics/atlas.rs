@@ -1,64 +1,152 @@
 use std::collections::BTreeMap;
 use std::hash::Hash;
 use std::fmt::Debug;
+use std::sync::Arc;
 
+use anyhow::{Result, bail};
 use euclid::Box2D;
-use image::{DynamicImage, GenericImage, RgbaImage};
+use image::{DynamicImage, RgbaImage, imageops::{self, FilterType}};
 use rectangle_pack::{GroupedRectsToPlace, RectToInsert, TargetBin, volume_heuristic, contains_smallest_box, pack_rects, PackedLocation};
 use wgpu::{FilterMode, Device, Queue};
 
 use super::{texture::Texture, bindable::Bindable};
 
+/// Images packed into as many `ATLAS_SIZE`² pages as needed, uploaded as the layers of an array texture.
+/// Every image is surrounded by copies of its border pixels, so mipmaps and filtering don't bleed neighbouring images in.
 pub struct Atlas<RectToPlaceId: Debug + Hash + Clone + Eq + Ord + PartialOrd> {
-    locations: BTreeMap<RectToPlaceId, (i32, PackedLocation)>,
+    map: Arc<AtlasMap<RectToPlaceId>>,
     texture: Texture,
 }
 
+/// Placement of every image inside of the atlas, kept separately from the GPU texture
+/// so UV lookups can be shared with other threads.
+pub struct AtlasMap<RectToPlaceId: Debug + Hash + Clone + Eq + Ord + PartialOrd> {
+    placements: BTreeMap<RectToPlaceId, Placement>,
+}
+
+// Where an image ended up, without the padding around it
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    page   : u32,
+    x      : u32,
+    y      : u32,
+    width  : u32,
+    height : u32,
+}
+
 impl<RectToPlaceId: Debug + Hash + Clone + Copy + Eq + Ord + PartialOrd> Atlas<RectToPlaceId> {
-    const ATLAS_SIZE: u32 = 2048;
+    const ATLAS_SIZE: u32 = AtlasMap::<RectToPlaceId>::ATLAS_SIZE;
+    const PADDING: u32 = AtlasMap::<RectToPlaceId>::PADDING;
+
+    // Enough levels to make 16x16 textures 2x2, the padding leaves every level with at least a pixel of border
+    const MIP_LEVELS: u32 = 4;
+
+    // Sizes are rounded up to this, placements being sums of sizes every image then starts on a whole pixel of every mip level
+    const ALIGNMENT: u32 = 1 << (Self::MIP_LEVELS - 1);
+
+    pub fn new(images: &[(RectToPlaceId, DynamicImage)], device: &Device, queue: &Queue, label: Option<&str>) -> Result<Self> {
+        let padded = |size: u32| (size + 2 * Self::PADDING).next_multiple_of(Self::ALIGNMENT);
 
-    pub fn new(images: &[(RectToPlaceId, DynamicImage)], device: &Device, queue: &Queue, label: Option<&str>) -> Self {
         let mut rects_to_place = GroupedRectsToPlace::<RectToPlaceId, i32>::new();
         for (id, image) in images {
-            rects_to_place.push_rect(*id, None, RectToInsert::new(image.width(), image.height(), 255));
+            if padded(image.width()) > Self::ATLAS_SIZE || padded(image.height()) > Self::ATLAS_SIZE {
+                bail!("Texture {:?} of {}x{} doesn't fit into a {}² atlas page", id, image.width(), image.height(), Self::ATLAS_SIZE);
+            }
+
+            rects_to_place.push_rect(*id, None, RectToInsert::new(padded(image.width()), padded(image.height()), 1));
         }
 
-        let mut target_bins = BTreeMap::new(); // TODO: use multiple atlases if needed
-        target_bins.insert(0, TargetBin::new(Self::ATLAS_SIZE, Self::ATLAS_SIZE, 255));
+        // Pages are filled in order, so at worst every image ends up on a page of its own
+        let mut target_bins = BTreeMap::new();
+        for page in 0 .. images.len().max(1) as u32 {
+            target_bins.insert(page, TargetBin::new(Self::ATLAS_SIZE, Self::ATLAS_SIZE, 1));
+        }
 
-        let rectangle_placements = pack_rects(
-            &rects_to_place,
-            &mut target_bins,
-            &volume_heuristic,
-            &contains_smallest_box
-        ).unwrap();
+        let rectangle_placements = match pack_rects(&rects_to_place, &mut target_bins, &volume_heuristic, &contains_smallest_box) {
+            Ok(placements) => placements,
+            Err(error) => bail!("Failed to pack the texture atlas: {}", error),
+        };
 
-        let mut texture_image = RgbaImage::new(Self::ATLAS_SIZE, Self::ATLAS_SIZE);
         let locations = rectangle_placements.packed_locations().clone();
+        let page_count = locations.values().map(|(page, _)| page + 1).max().unwrap_or(1);
+        let mut pages = vec![RgbaImage::new(Self::ATLAS_SIZE, Self::ATLAS_SIZE); page_count as usize];
+        let mut placements = BTreeMap::new();
         for (id, image) in images {
-            let loc = locations[&id].1;
-            texture_image.copy_from(image, loc.x(), loc.y()).unwrap();
+            let (page, location) = locations[id];
+            Self::copy_padded(&mut pages[page as usize], &image.to_rgba8(), location);
+            placements.insert(*id, Placement {
+                page,
+                x      : location.x() + Self::PADDING,
+                y      : location.y() + Self::PADDING,
+                width  : image.width(),
+                height : image.height(),
+            });
         }
 
-        let texture = Texture::from_image(device, queue, &DynamicImage::from(texture_image), FilterMode::Nearest, label).unwrap();
-        return Self {
+        let layers: Vec<Vec<RgbaImage>> = pages.into_iter().map(|page| {
+            let mut levels = vec![page];
+            for level in 1 .. Self::MIP_LEVELS {
+                let size = Self::ATLAS_SIZE >> level;
+                levels.push(imageops::resize(levels.last().unwrap(), size, size, FilterType::Triangle));
+            }
+
+            levels
+        }).collect();
+
+        let texture = Texture::from_layers(device, queue, &layers, FilterMode::Nearest, label)?;
+        return Ok(Self {
             texture,
-            locations,
-        };
+            map: Arc::new(AtlasMap { placements }),
+        });
     }
 
+    pub fn uv(&self, id: &RectToPlaceId) -> Box2D<f32, f32> {
+        return self.map.uv(id);
+    }
+
+    pub fn page(&self, id: &RectToPlaceId) -> u32 {
+        return self.map.page(id);
+    }
+
+    pub fn map(&self) -> Arc<AtlasMap<RectToPlaceId>> {
+        return self.map.clone();
+    }
+
+    // Copies the image into the padded location, filling the padding by extending its border pixels outwards
+    fn copy_padded(page: &mut RgbaImage, image: &RgbaImage, location: PackedLocation) {
+        let (width, height) = image.dimensions();
+        for y in 0 .. location.height() {
+            for x in 0 .. location.width() {
+                let source_x = x.saturating_sub(Self::PADDING).min(width - 1);
+                let source_y = y.saturating_sub(Self::PADDING).min(height - 1);
+                page.put_pixel(location.x() + x, location.y() + y, *image.get_pixel(source_x, source_y));
+            }
+        }
+    }
+}
+
+impl<RectToPlaceId: Debug + Hash + Clone + Copy + Eq + Ord + PartialOrd> AtlasMap<RectToPlaceId> {
+    const ATLAS_SIZE: u32 = 2048;
+    const PADDING: u32 = 8;
+
+    /// Region of the image on its page, without the padding.
     pub fn uv(&self, id: &RectToPlaceId) -> Box2D<f32, f32> {
         let atlas_size = Self::ATLAS_SIZE as f32;
-        let location = self.locations[id].1;
+        let placement = self.placements[id];
+
+        let pos = (placement.x as f32 / atlas_size,
+                   placement.y as f32 / atlas_size).into();
 
-        let pos = (location.x() as f32 / atlas_size,
-                   location.y() as f32 / atlas_size).into();
-                   
-        let size = ((location.x() + location.width())  as f32 / atlas_size,
-                    (location.y() + location.height()) as f32 / atlas_size).into();
+        let size = ((placement.x + placement.width)  as f32 / atlas_size,
+                    (placement.y + placement.height) as f32 / atlas_size).into();
 
         return Box2D::new(pos, size);
     }
+
+    /// Atlas page, the texture array layer the image is on.
+    pub fn page(&self, id: &RectToPlaceId) -> u32 {
+        return self.placements[id].page;
+    }
 }
 
 impl<RectToPlaceId: Debug + Hash + Clone + Copy + Eq + Ord + PartialOrd> Bindable for Atlas<RectToPlaceId> {
@@ -69,4 +157,4 @@ impl<RectToPlaceId: Debug + Hash + Clone + Copy + Eq + Ord + PartialOrd> Bindabl
     fn layout(&self) -> &wgpu::BindGroupLayout {
         return self.texture.layout();
     }
-}
\ No newline at end of file
+}
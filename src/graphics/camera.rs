@@ -24,13 +24,18 @@ impl Camera {
         };
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    /// Unit vector the camera is looking along.
+    pub fn direction(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
 
+        return Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize();
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
         return Matrix4::look_to_rh( // This is sad.
             (self.position.x, self.position.y, self.position.z).into(),
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
+            self.direction(),
             Vector3::unit_y(),
         );
     }
@@ -0,0 +1,135 @@
+use cgmath::{Matrix4, Vector3, Vector4, InnerSpace};
+
+/// The volume a camera can see, as 6 inward-facing planes `(normal, distance)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view-projection matrix (Gribb & Hartmann).
+    /// Expects wgpu clip space, where depth goes from 0 to 1, like `calc_view_proj` gives.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let planes = [
+            w + x, // Left
+            w - x, // Right
+            w + y, // Bottom
+            w - y, // Top
+            z,     // Near
+            w - z, // Far
+        ];
+
+        return Self { planes: planes.map(|plane| plane / plane.truncate().magnitude()) };
+    }
+
+    /// Returns `false` only if the box lies fully outside of the frustum.
+    /// Boxes near the corners of the frustum can pass without actually being visible.
+    pub fn intersects_aabb(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        for plane in &self.planes {
+            // Corner of the box furthest along the plane's normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+
+            if plane.truncate().dot(corner) + plane.w < 0.0 {
+                return false;
+            }
+        }
+
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, vec3};
+
+    use super::*;
+    use crate::graphics::camera::{Camera, Projection, calc_view_proj};
+
+    const NEAR: f32 = 0.1;
+    const FAR: f32 = 100.0;
+
+    // Camera at the origin looking along +X, with a 90° field of view so the side planes are at 45°
+    fn frustum() -> Frustum {
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(0.0));
+        let projection = Projection::new(100, 100, Deg(90.0), NEAR, FAR);
+        return Frustum::from_matrix(&calc_view_proj(&camera, &projection));
+    }
+
+    fn inside(frustum: &Frustum, point: Vector3<f32>) -> bool {
+        return frustum.planes.iter().all(|plane| plane.truncate().dot(point) + plane.w >= 0.0);
+    }
+
+    #[test]
+    fn planes_are_normalized() {
+        for plane in frustum().planes {
+            assert!((plane.truncate().magnitude() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn near_plane_uses_wgpu_depth() {
+        // Depth starts at 0 in wgpu, so the near plane is right at `NEAR` in front of the camera
+        let near = frustum().planes[4];
+        assert!((near.truncate() - vec3(1.0, 0.0, 0.0)).magnitude() < 1e-4);
+        assert!((near.w + NEAR).abs() < 1e-4);
+
+        assert!(!inside(&frustum(), vec3(NEAR * 0.5, 0.0, 0.0)));
+        assert!(inside(&frustum(), vec3(NEAR * 1.5, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn planes_bound_the_view() {
+        let frustum = frustum();
+        assert!(inside(&frustum, vec3(10.0, 0.0, 0.0)));
+        assert!(inside(&frustum, vec3(FAR - 1.0, 0.0, 0.0)));
+        assert!(!inside(&frustum, vec3(FAR + 1.0, 0.0, 0.0)));
+
+        for (y, z) in [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+            assert!(inside(&frustum, vec3(10.0, 9.0 * y, 9.0 * z)));
+            assert!(!inside(&frustum, vec3(10.0, 11.0 * y, 11.0 * z)));
+        }
+    }
+
+    #[test]
+    fn boxes_inside_outside_and_straddling() {
+        let frustum = frustum();
+        let cube = |x: f32, y: f32, z: f32, size: f32| frustum.intersects_aabb(vec3(x, y, z), vec3(x + size, y + size, z + size));
+
+        // Inside
+        assert!(cube(5.0, -0.5, -0.5, 1.0));
+        assert!(cube(50.0, -10.0, -10.0, 20.0));
+
+        // Fully outside of a single plane
+        assert!(!cube(5.0, 20.0, 0.0, 1.0));
+        assert!(!cube(5.0, -21.0, 0.0, 1.0));
+        assert!(!cube(5.0, 0.0, 20.0, 1.0));
+        assert!(!cube(5.0, 0.0, -21.0, 1.0));
+        assert!(!cube(FAR + 1.0, 0.0, 0.0, 1.0));
+
+        // Straddling a plane
+        assert!(cube(5.0, 4.0, 0.0, 3.0));
+        assert!(cube(5.0, 0.0, -7.0, 3.0));
+        assert!(cube(FAR - 1.0, 0.0, 0.0, 2.0));
+
+        // Containing the whole frustum, or the camera
+        assert!(frustum.intersects_aabb(vec3(-500.0, -500.0, -500.0), vec3(500.0, 500.0, 500.0)));
+        assert!(cube(-1.0, -1.0, -1.0, 2.0));
+    }
+
+    #[test]
+    fn boxes_behind_the_camera() {
+        let frustum = frustum();
+        assert!(!frustum.intersects_aabb(vec3(-6.0, -0.5, -0.5), vec3(-5.0, 0.5, 0.5)));
+        assert!(!frustum.intersects_aabb(vec3(-60.0, -50.0, -50.0), vec3(-5.0, 50.0, 50.0)));
+
+        // Between the camera and the near plane
+        assert!(!frustum.intersects_aabb(vec3(0.01, -0.01, -0.01), vec3(NEAR * 0.5, 0.01, 0.01)));
+    }
+}
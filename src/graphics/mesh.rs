@@ -1,8 +1,8 @@
 use bytemuck::{Zeroable, Pod};
-use cgmath::{Vector3, Vector2};
+use cgmath::{Vector2, Vector3, Vector4};
 use wgpu::util::DeviceExt;
 
-use super::drawable::Drawable;
+use super::{drawable::Drawable, utils::Side};
 
 unsafe impl bytemuck::Pod for Vertex {}
 unsafe impl bytemuck::Zeroable for Vertex {}
@@ -10,12 +10,15 @@ unsafe impl bytemuck::Zeroable for Vertex {}
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
-    pub pos : Vector3<f32>,
-    pub uv  : Vector2<f32>,
+    pub pos   : Vector3<f32>,
+    pub uv    : Vector2<f32>, // In tiles, repeats every 1.0
+    pub tile  : Vector4<f32>, // Texture region (min.x, min.y, max.x, max.y) that `uv` maps onto
+    pub light : Vector2<f32>, // Sky and block light, from 0.0 to 1.0
+    pub ao    : f32,          // Ambient occlusion, 0.0 is fully occluded
 }
 
 impl Vertex {
-    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2, 2 => Float32x4, 3 => Float32x2, 4 => Float32];
 
     pub fn describe<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -28,6 +31,86 @@ impl Vertex {
     }
 }
 
+/// Vertex of chunk meshes packed into two words, unpacked by `chunk.wgsl`:
+/// - x, y and z inside of the chunk (6 bits each, 0 to 32), side (3 bits), ambient occlusion (2 bits),
+///   sky and block light (4 bits each)
+/// - u and v in tiles (7 bits each, -32 to 32 stored with an offset of 32) and the texture, an index into the tile table
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChunkVertex {
+    pub data: [u32; 2],
+}
+
+unsafe impl bytemuck::Pod for ChunkVertex {}
+unsafe impl bytemuck::Zeroable for ChunkVertex {}
+
+impl ChunkVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Uint32x2];
+
+    pub fn new(pos: Vector3<u32>, side: Side, uv: Vector2<i32>, texture: u16, light: [u8; 2], ao: u8) -> Self {
+        let position = pos.x | (pos.y << 6) | (pos.z << 12);
+        let shading = (side as u32) << 18 | (ao as u32) << 21 | (light[0] as u32) << 23 | (light[1] as u32) << 27;
+        let uv = (uv.x + 32) as u32 | ((uv.y + 32) as u32) << 7;
+
+        return Self {
+            data: [position | shading, uv | (texture as u32) << 14],
+        };
+    }
+
+    pub fn position(&self) -> Vector3<u32> {
+        return Vector3::new(self.data[0] & 63, (self.data[0] >> 6) & 63, (self.data[0] >> 12) & 63);
+    }
+
+    pub fn describe<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+
+        wgpu::VertexBufferLayout {
+            array_stride : mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode    : wgpu::VertexStepMode::Vertex,
+            attributes   : &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Indices drawing every four vertices as a quad made of triangles (0, 1, 2) and (2, 3, 0).
+/// One buffer is shared by all meshes, it grows to fit the largest one.
+pub struct QuadIndices {
+    buffer : wgpu::Buffer,
+    quads  : u32,
+}
+
+impl QuadIndices {
+    pub fn new(device: &wgpu::Device, quads: u32) -> Self {
+        return Self {
+            buffer: Self::make_buffer(device, quads),
+            quads,
+        };
+    }
+
+    /// Makes sure meshes of up to `quads` quads can be drawn.
+    pub fn reserve(&mut self, device: &wgpu::Device, quads: u32) {
+        if quads > self.quads {
+            self.quads = quads.next_power_of_two();
+            self.buffer = Self::make_buffer(device, self.quads);
+        }
+    }
+
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_index_buffer(self.buffer.slice(..), wgpu::IndexFormat::Uint32);
+    }
+
+    fn make_buffer(device: &wgpu::Device, quads: u32) -> wgpu::Buffer {
+        let indices: Vec<u32> = (0 .. quads).flat_map(|quad| [0, 1, 2, 2, 3, 0].map(|index| quad * 4 + index)).collect();
+        return device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Quad Index Buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+    }
+}
+
 // Mesh
 pub struct Mesh {
         buffer   : wgpu::Buffer,
@@ -180,7 +263,7 @@ impl InstancedMesh {
 
 impl Drawable for InstancedMesh {
     fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
-        if self.instances.len() > 0 {
+        if !self.instances.is_empty() && !self.vertices.is_empty() {
             render_pass.set_vertex_buffer(0, self.buffer.slice(..));
             render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
             render_pass.draw(0 .. self.vertices.len() as u32, 0 .. self.instances.len() as u32);
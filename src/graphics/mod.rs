@@ -7,4 +7,5 @@ pub mod bindable;
 pub mod drawable;
 pub mod depth_buffer;
 pub mod atlas;
-pub mod uniform;
\ No newline at end of file
+pub mod uniform;
+pub mod frustum;
\ No newline at end of file
@@ -1,6 +1,6 @@
-use image::{GenericImageView, DynamicImage};
+use image::{DynamicImage, RgbaImage};
 use wgpu::{Device, Queue, FilterMode};
-use anyhow::Result;
+use anyhow::{Result, bail};
 
 use super::bindable::Bindable;
 
@@ -28,19 +28,42 @@ impl Texture {
                       img         : &DynamicImage,
                       filter_mode : FilterMode,
                       label       : Option<&str>) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        return Self::create(device, queue, &[vec![img.to_rgba8()]], wgpu::TextureViewDimension::D2, filter_mode, label);
+    }
+
+    /// Array texture, bound as `texture_2d_array`. Every layer comes with its whole mip chain,
+    /// each level half the size of the previous one, and all layers must have the same amount of levels.
+    pub fn from_layers(device      : &Device,
+                       queue       : &Queue,
+                       layers      : &[Vec<RgbaImage>],
+                       filter_mode : FilterMode,
+                       label       : Option<&str>) -> Result<Self> {
+        return Self::create(device, queue, layers, wgpu::TextureViewDimension::D2Array, filter_mode, label);
+    }
+
+    fn create(device      : &Device,
+              queue       : &Queue,
+              layers      : &[Vec<RgbaImage>],
+              dimension   : wgpu::TextureViewDimension,
+              filter_mode : FilterMode,
+              label       : Option<&str>) -> Result<Self> {
+        let mip_level_count = layers.first().map_or(0, |levels| levels.len());
+        if mip_level_count == 0 || layers.iter().any(|levels| levels.len() != mip_level_count) {
+            bail!("Texture layers must all have the same, non-zero amount of mip levels");
+        }
+
+        let dimensions = layers[0][0].dimensions();
         let size = wgpu::Extent3d {
             width                 : dimensions.0,
             height                : dimensions.1,
-            depth_or_array_layers : 1,
+            depth_or_array_layers : layers.len() as u32,
         };
 
         let texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 label,
                 size,
-                mip_level_count : 1,
+                mip_level_count : mip_level_count as u32,
                 sample_count    : 1,
                 dimension       : wgpu::TextureDimension::D2,
                 format          : wgpu::TextureFormat::Rgba8UnormSrgb,
@@ -49,25 +72,34 @@ impl Texture {
             }
         );
 
-        // Upload texture
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect    : wgpu::TextureAspect::All,
-                texture   : &texture,
-                mip_level : 0,
-                origin    : wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset         : 0,
-                bytes_per_row  : std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image : std::num::NonZeroU32::new(dimensions.1),
-            },
-            size,
-        );
+        // Upload every level of every layer
+        for (layer, levels) in layers.iter().enumerate() {
+            for (level, image) in levels.iter().enumerate() {
+                let (width, height) = image.dimensions();
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        aspect    : wgpu::TextureAspect::All,
+                        texture   : &texture,
+                        mip_level : level as u32,
+                        origin    : wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                    },
+                    image,
+                    wgpu::ImageDataLayout {
+                        offset         : 0,
+                        bytes_per_row  : std::num::NonZeroU32::new(4 * width),
+                        rows_per_image : std::num::NonZeroU32::new(height),
+                    },
+                    wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+                );
+            }
+        }
 
         // Shaders stuff
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(dimension),
+            .. Default::default()
+        });
+
         let sampler = device.create_sampler(
             &wgpu::SamplerDescriptor {
                 address_mode_u : wgpu::AddressMode::ClampToEdge,
@@ -75,7 +107,7 @@ impl Texture {
                 address_mode_w : wgpu::AddressMode::ClampToEdge,
                 mag_filter     : filter_mode,
                 min_filter     : filter_mode,
-                mipmap_filter  : wgpu::FilterMode::Nearest,
+                mipmap_filter  : wgpu::FilterMode::Linear,
                 .. Default::default()
             }
         );
@@ -88,7 +120,7 @@ impl Texture {
                     visibility : wgpu::ShaderStages::FRAGMENT,
                     ty         : wgpu::BindingType::Texture {
                         multisampled   : false,
-                        view_dimension : wgpu::TextureViewDimension::D2,
+                        view_dimension : dimension,
                         sample_type    : wgpu::TextureSampleType::Float { filterable: true },
                     },
                     count      : None,
@@ -1,6 +1,6 @@
 use wgpu::{Device, TextureView};
 
-use super::depth_buffer::DepthBuffer;
+use super::{depth_buffer::DepthBuffer, mesh::{ChunkVertex, InstanceRaw}};
 
 pub fn render_pass<'a>(encoder: &'a mut wgpu::CommandEncoder, view: &'a wgpu::TextureView, depth_buffer: Option<&'a TextureView>) -> wgpu::RenderPass<'a> {
     return encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -56,6 +56,17 @@ pub fn pipeline(device  : &Device,
                 config  : &wgpu::SurfaceConfiguration,
                 groups  : &[&wgpu::BindGroupLayout],
                 buffers : &[wgpu::VertexBufferLayout]) -> wgpu::RenderPipeline {
+    return blended_pipeline(device, shader, config, groups, buffers, false);
+}
+
+/// Same as `pipeline`, but a `translucent` one blends with what's already drawn using the fragment's alpha,
+/// runs `fragment_translucent` instead of `fragment_main` and leaves the depth buffer untouched.
+pub fn blended_pipeline(device      : &Device,
+                        shader      : &wgpu::ShaderModule,
+                        config      : &wgpu::SurfaceConfiguration,
+                        groups      : &[&wgpu::BindGroupLayout],
+                        buffers     : &[wgpu::VertexBufferLayout],
+                        translucent : bool) -> wgpu::RenderPipeline {
     let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Render Pipeline Layout"),
         push_constant_ranges : &[],
@@ -74,10 +85,10 @@ pub fn pipeline(device  : &Device,
         },
         fragment: Some(wgpu::FragmentState {
             module      : &shader,
-            entry_point : "fragment_main",
+            entry_point : if translucent { "fragment_translucent" } else { "fragment_main" },
             targets     : &[wgpu::ColorTargetState {
                 format     : config.format,
-                blend      : Some(wgpu::BlendState::REPLACE),
+                blend      : Some(if translucent { wgpu::BlendState::ALPHA_BLENDING } else { wgpu::BlendState::REPLACE }),
                 write_mask : wgpu::ColorWrites::ALL,
             }],
         }),
@@ -94,7 +105,7 @@ pub fn pipeline(device  : &Device,
         },
         depth_stencil: Some(wgpu::DepthStencilState {
             format              : DepthBuffer::DEPTH_FORMAT,
-            depth_write_enabled : true,
+            depth_write_enabled : !translucent,
             depth_compare       : wgpu::CompareFunction::Less,
             stencil             : wgpu::StencilState::default(),
             bias                : wgpu::DepthBiasState::default(),
@@ -108,7 +119,20 @@ pub fn pipeline(device  : &Device,
     });
 }
 
-#[derive(Clone, Copy)]
+/// Pipeline for chunk meshes made of `ChunkVertex`es, drawn with `chunk.wgsl`.
+/// Bind groups: texture atlas, camera, tile table.
+pub fn chunk_pipeline(device      : &Device,
+                      shader      : &wgpu::ShaderModule,
+                      config      : &wgpu::SurfaceConfiguration,
+                      groups      : &[&wgpu::BindGroupLayout],
+                      translucent : bool) -> wgpu::RenderPipeline {
+    return blended_pipeline(device, shader, config, groups, &[
+        ChunkVertex::describe(),
+        InstanceRaw::describe(),
+    ], translucent);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
     Top    , // Y+
     Bottom , // Y-
@@ -118,4 +142,20 @@ pub enum Side {
     
     Front  , // X+
     Back   , // X-
+}
+
+impl Side {
+    pub const ALL: [Side; 6] = [Side::Top, Side::Bottom, Side::Right, Side::Left, Side::Front, Side::Back];
+
+    // Unit vector pointing out of the face
+    pub const fn normal(&self) -> (isize, isize, isize) {
+        return match self {
+            Side::Top    => ( 0,  1,  0),
+            Side::Bottom => ( 0, -1,  0),
+            Side::Right  => ( 0,  0,  1),
+            Side::Left   => ( 0,  0, -1),
+            Side::Front  => ( 1,  0,  0),
+            Side::Back   => (-1,  0,  0),
+        };
+    }
 }
\ No newline at end of file
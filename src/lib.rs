@@ -3,6 +3,7 @@ pub mod game;
 pub mod graphics;
 pub mod screen;
 pub mod utils;
+pub mod resources;
 pub mod egui;
 
 use game::client::game::Game;
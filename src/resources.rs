@@ -0,0 +1,293 @@
+use std::{env, fs::{self, File}, io::{ErrorKind, Read}, path::{Path, PathBuf}, sync::Mutex};
+
+use anyhow::{Result, Context, anyhow, bail};
+use image::DynamicImage;
+use log::{info, error};
+use zip::{ZipArchive, result::ZipError};
+
+/// A set of resource files: textures, shaders and block definitions.
+/// Files are addressed with `/` separated paths relative to the pack's root, laid out like the `res` directory.
+pub trait ResourcePack {
+    fn name(&self) -> &str;
+
+    /// Returns `None` if the pack doesn't have the file.
+    fn read(&self, path: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// Files shipped inside of the executable, every other pack falls back to these.
+pub struct BuiltinPack;
+
+impl BuiltinPack {
+    // Everything in `res`, listed by the build script
+    const FILES: &'static [(&'static str, &'static [u8])] = include!(concat!(env!("OUT_DIR"), "/builtin_resources.rs"));
+}
+
+impl ResourcePack for BuiltinPack {
+    fn name(&self) -> &str {
+        return "builtin";
+    }
+
+    fn read(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        return Ok(Self::FILES.iter().find(|(name, _)| *name == path).map(|(_, bytes)| bytes.to_vec()));
+    }
+}
+
+/// Pack unpacked into a directory.
+pub struct DirectoryPack {
+    name : String,
+    root : PathBuf,
+}
+
+impl DirectoryPack {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        return Self { name: root.display().to_string(), root };
+    }
+}
+
+impl ResourcePack for DirectoryPack {
+    fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    fn read(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        return match fs::read(self.root.join(path)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error).with_context(|| format!("Failed to read {} from {}", path, self.name)),
+        };
+    }
+}
+
+/// Pack in a zip archive, with the files at the root of the archive.
+pub struct ZipPack {
+    name    : String,
+    archive : Mutex<ZipArchive<File>>, // Reading entries needs mutable access
+}
+
+impl ZipPack {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("Failed to open resource pack {:?}", path))?;
+        let archive = ZipArchive::new(file).with_context(|| format!("Resource pack {:?} is not a valid zip archive", path))?;
+
+        return Ok(Self { name: path.display().to_string(), archive: Mutex::new(archive) });
+    }
+}
+
+impl ResourcePack for ZipPack {
+    fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    fn read(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let mut archive = self.archive.lock().unwrap();
+        let mut file = match archive.by_name(path) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Ok(None),
+            Err(error) => return Err(error).with_context(|| format!("Failed to read {} from {}", path, self.name)),
+        };
+
+        let mut bytes = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut bytes).with_context(|| format!("Failed to read {} from {}", path, self.name))?;
+        return Ok(Some(bytes));
+    }
+}
+
+/// Stack of resource packs, a file is taken from the first pack that has it.
+/// The builtin pack is always at the bottom, so packs only need the files they change.
+pub struct ResourceManager {
+    packs: Vec<Box<dyn ResourcePack>>, // Highest priority first, builtin last
+}
+
+impl ResourceManager {
+    /// Just the builtin resources.
+    pub fn new() -> Self {
+        return Self {
+            packs: vec![Box::new(BuiltinPack)],
+        };
+    }
+
+    /// Packs from the `RESOURCE_PACKS` environment variable, a list of directories and zip archives
+    /// separated like `PATH`, most important first.
+    pub fn from_env() -> Result<Self> {
+        let mut resources = Self::new();
+        if let Some(paths) = env::var_os("RESOURCE_PACKS") {
+            let paths: Vec<_> = env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()).collect();
+            for path in paths.iter().rev() {
+                resources.push(Self::open(path)?);
+            }
+        }
+
+        return Ok(resources);
+    }
+
+    /// Opens a directory or zip archive as a resource pack.
+    pub fn open(path: impl AsRef<Path>) -> Result<Box<dyn ResourcePack>> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            return Ok(Box::new(DirectoryPack::new(path)));
+        }
+
+        if !path.exists() {
+            bail!("Resource pack {:?} doesn't exist", path);
+        }
+
+        return Ok(Box::new(ZipPack::open(path)?));
+    }
+
+    /// Puts a pack on top of the others.
+    pub fn push(&mut self, pack: Box<dyn ResourcePack>) {
+        info!("Using resource pack {}", pack.name());
+        self.packs.insert(0, pack);
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>> {
+        // Windows takes `\` as a separator too, and `C:` starts an absolute path.
+        let escapes = path.split(['/', '\\']).any(|component| component == "..");
+        if escapes || path.starts_with(['/', '\\']) || path.contains(':') {
+            bail!("Invalid resource path {:?}", path);
+        }
+
+        for pack in &self.packs {
+            if let Some(bytes) = pack.read(path)? {
+                return Ok(bytes);
+            }
+        }
+
+        bail!("Resource {:?} not found in any resource pack", path);
+    }
+
+    pub fn read_string(&self, path: &str) -> Result<String> {
+        return String::from_utf8(self.read(path)?).with_context(|| format!("Resource {:?} is not valid UTF-8", path));
+    }
+
+    /// Decodes an image, flipped so the first row is at the bottom like textures expect.
+    pub fn image(&self, path: &str) -> Result<DynamicImage> {
+        let image = image::load_from_memory(&self.read(path)?).with_context(|| format!("Resource {:?} is not a valid image", path))?;
+        return Ok(image.flipv());
+    }
+
+    /// Compiles a WGSL shader. One that doesn't compile is replaced by the builtin shader, so a broken pack can't take the game down.
+    pub fn shader(&self, device: &wgpu::Device, path: &str) -> Result<wgpu::ShaderModule> {
+        let source = self.read_string(path)?;
+        let error = match Self::compile(device, path, &source) {
+            Ok(module) => return Ok(module),
+            Err(error) => error,
+        };
+
+        let builtin = match BuiltinPack.read(path)? {
+            Some(builtin) if builtin != source.as_bytes() => String::from_utf8(builtin)?,
+            _ => return Err(error),
+        };
+
+        error!("{:#}, using the builtin one instead", error);
+        return Self::compile(device, path, &builtin);
+    }
+
+    // wgpu panics on invalid shaders unless the error is caught with an error scope
+    fn compile(device: &wgpu::Device, path: &str, source: &str) -> Result<wgpu::ShaderModule> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label  : Some(path),
+            source : wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            return Err(anyhow!("Shader {:?} is invalid: {}", path, error));
+        }
+
+        return Ok(module);
+    }
+}
+
+impl Default for ResourceManager {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::atomic::{AtomicUsize, Ordering}};
+
+    use zip::{ZipWriter, write::FileOptions};
+
+    use super::*;
+
+    // Fresh directory per test, tests run in parallel
+    fn directory() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let directory = env::temp_dir().join(format!("voxelgame-resources-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        return directory;
+    }
+
+    fn zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, bytes) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn builtin_pack_has_everything_in_res() {
+        let resources = ResourceManager::new();
+        for entry in fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("res")).unwrap() {
+            let entry = entry.unwrap();
+            assert_eq!(resources.read(entry.file_name().to_str().unwrap()).unwrap(), fs::read(entry.path()).unwrap());
+        }
+
+        assert!(resources.read("missing.png").is_err());
+    }
+
+    #[test]
+    fn packs_override_in_order() {
+        let directory = directory();
+        let pack = directory.join("pack");
+        fs::create_dir_all(&pack).unwrap();
+        fs::write(pack.join("blocks.ron"), "directory").unwrap();
+        fs::write(pack.join("extra.txt"), "directory").unwrap();
+        zip(&directory.join("pack.zip"), &[("blocks.ron", b"zip"), ("only-zip.txt", b"zip")]);
+
+        let mut resources = ResourceManager::new();
+        resources.push(ResourceManager::open(&pack).unwrap());
+        resources.push(ResourceManager::open(directory.join("pack.zip")).unwrap());
+
+        assert_eq!(resources.read_string("blocks.ron").unwrap(), "zip");
+        assert_eq!(resources.read_string("extra.txt").unwrap(), "directory");
+        assert_eq!(resources.read_string("only-zip.txt").unwrap(), "zip");
+        assert_eq!(resources.read("core.wgsl").unwrap(), BuiltinPack.read("core.wgsl").unwrap().unwrap());
+
+        assert!(ResourceManager::open(directory.join("missing")).is_err());
+        fs::write(directory.join("broken.zip"), "not a zip").unwrap();
+        assert!(ResourceManager::open(directory.join("broken.zip")).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn paths_cant_leave_the_pack() {
+        let directory = directory();
+        let pack = directory.join("pack");
+        fs::create_dir_all(pack.join("textures")).unwrap();
+        fs::write(directory.join("secret.txt"), "secret").unwrap();
+        fs::write(pack.join("textures").join("ok.txt"), "ok").unwrap();
+
+        let mut resources = ResourceManager::new();
+        resources.push(Box::new(DirectoryPack::new(&pack)));
+        assert_eq!(resources.read_string("textures/ok.txt").unwrap(), "ok");
+
+        let secret = directory.join("secret.txt").display().to_string();
+        for path in ["../secret.txt", "textures/../../secret.txt", "..\\secret.txt", "textures\\..\\..\\secret.txt", "/etc/passwd", "\\secret.txt", "C:\\secret.txt", "C:secret.txt", &secret] {
+            assert!(resources.read(path).is_err(), "{:?} was allowed", path);
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use std::{env, fs, net::SocketAddr, path::PathBuf, sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+use cgmath::vec3;
+use uuid::Uuid as UUID;
+use voxelgame::{game::{client::world::{block_registry::BlockRegistry, terrain_generator::NoiseTerrainGenerator}, net::proto::{ClientPacket, ServerPacket, PROTOCOL_VERSION}, server::{server::{Server, TICK}, region::RegionStorage}}, resources::ResourceManager};
+
+// Fresh world directory per test, tests run in parallel
+fn directory() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let directory = env::temp_dir().join(format!("voxelgame-server-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+    let _ = fs::remove_dir_all(&directory);
+
+    return directory;
+}
+
+fn server(directory: &PathBuf) -> Server {
+    let registry = BlockRegistry::load(&ResourceManager::new()).unwrap();
+    let generator = NoiseTerrainGenerator::new(1, registry.by_name("panel").unwrap(), registry.by_name("test").unwrap());
+    return Server::new(1, registry, Box::new(generator), RegionStorage::new(directory).unwrap());
+}
+
+fn address(port: u16) -> SocketAddr {
+    return SocketAddr::from(([127, 0, 0, 1], port));
+}
+
+fn send(server: &mut Server, address: SocketAddr, packet: &ClientPacket) {
+    server.receive(address, bincode::serialize(packet).unwrap());
+}
+
+// Takes the packets sent to the address out of the outbox
+fn sent_to(server: &mut Server, address: SocketAddr) -> Vec<ServerPacket> {
+    let (sent, rest) = server.outbox.drain(..).partition(|(to, _, _)| *to == address);
+    server.outbox = rest;
+
+    return sent.into_iter().map(|(_, _, bytes): (_, _, Vec<u8>)| bincode::deserialize(&bytes).unwrap()).collect();
+}
+
+// Connects a player and returns their UUID and token
+fn join(server: &mut Server, address: SocketAddr, name: &str) -> (UUID, UUID) {
+    send(server, address, &ClientPacket::Connect { protocol_version: PROTOCOL_VERSION, name: name.into() });
+    server.tick(TICK);
+
+    for packet in sent_to(server, address) {
+        if let ServerPacket::JoinAccepted { uuid, token, world, .. } = packet {
+            assert_eq!(world.seed, 1);
+            return (uuid, token);
+        }
+    }
+
+    panic!("{} wasn't accepted", name);
+}
+
+#[test]
+fn connecting_players_are_accepted() {
+    let directory = directory();
+    let mut server = server(&directory);
+
+    let (uuid, _) = join(&mut server, address(1), "first");
+    assert!(server.players.contains_key(&uuid));
+
+    // The name is taken now
+    send(&mut server, address(2), &ClientPacket::Connect { protocol_version: PROTOCOL_VERSION, name: "first".into() });
+    send(&mut server, address(3), &ClientPacket::Connect { protocol_version: PROTOCOL_VERSION + 1, name: "third".into() });
+    server.tick(TICK);
+    assert!(matches!(sent_to(&mut server, address(2))[..], [ServerPacket::JoinRejected { .. }]));
+    assert!(matches!(sent_to(&mut server, address(3))[..], [ServerPacket::JoinRejected { .. }]));
+    assert_eq!(server.players.len(), 1);
+
+    // Everyone else hears about new players
+    let (second, _) = join(&mut server, address(4), "second");
+    assert!(sent_to(&mut server, address(1)).iter().any(|packet| matches!(packet, ServerPacket::PlayerJoin { uuid, .. } if *uuid == second)));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn silent_players_are_evicted() {
+    let directory = directory();
+    let mut server = server(&directory);
+    server.player_timeout = Duration::from_secs(1);
+
+    let (silent, _) = join(&mut server, address(1), "silent");
+    let (talkative, token) = join(&mut server, address(2), "talkative");
+    sent_to(&mut server, address(2));
+
+    let ticks = server.player_timeout.as_millis() / TICK.as_millis() + 1;
+    for _ in 0 .. ticks {
+        send(&mut server, address(2), &ClientPacket::KeepAlive { token, uuid: talkative });
+        server.tick(TICK);
+    }
+
+    assert!(!server.players.contains_key(&silent));
+    assert!(server.players.contains_key(&talkative));
+    assert_eq!(server.disconnected, [address(1)]);
+    assert!(sent_to(&mut server, address(2)).iter().any(|packet| matches!(packet, ServerPacket::PlayerLeave { uuid } if *uuid == silent)));
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn broken_chunks_dont_stop_the_server() {
+    let directory = directory();
+    let mut server = server(&directory);
+    let (uuid, token) = join(&mut server, address(1), "player");
+
+    // The region of the second chunk is corrupted
+    let broken = vec3(8, 0, 0);
+    let region = RegionStorage::region(broken);
+    fs::write(directory.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z)), "not a region").unwrap();
+
+    send(&mut server, address(1), &ClientPacket::RequestChunks { token, uuid, positions: vec![vec3(0, 0, 0), broken] });
+    server.tick(TICK);
+    server.tick(TICK);
+
+    let positions: Vec<_> = sent_to(&mut server, address(1)).into_iter().filter_map(|packet| match packet {
+        ServerPacket::ChunkData { position, .. } => Some(position),
+        _ => None,
+    }).collect();
+
+    assert_eq!(positions, [vec3(0, 0, 0)]);
+    assert!(server.players.contains_key(&uuid));
+
+    fs::remove_dir_all(directory).unwrap();
+}